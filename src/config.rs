@@ -2,17 +2,30 @@ use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs::read_to_string;
 
+use crate::functions::{size_width, time_width};
 use crate::state::FX_CONFIG_DIR;
 
 const CONFIG_FILE: &str = "config.toml";
+pub const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+pub const RELATIVE_TIME: &str = "relative";
 
 pub const CONFIG_EXAMPLE: &str = "
 # default exec command when open files
 default = \"nvim\"
 
-# default key for sorting item list (\"Name\" or \"Time\")
+# default key for sorting item list (\"Name\", \"Time\" or \"Size\")
 sort_by = \"Name\"
 
+# what to do when putting an item whose name already exists in the directory:
+# \"Ask\", \"Rename\" (add the suffix \"_copied\"), \"Overwrite\" (merge for directories) or \"Skip\"
+on_conflict = \"Ask\"
+
+# (optional) open files made with `n` in the editor ($EDITOR, or the default command above)
+# open_new_file = true
+
+# (optional) author written in templates as {{author}} ($USER if not set)
+# author = \"Your Name\"
+
 # key(command you want to use) = values(extensions)
 [exec]
 feh = [\"jpg\", \"jpeg\", \"png\", \"gif\", \"svg\"]
@@ -43,20 +56,73 @@ zathura = [\"pdf\"]
 dir_fg = \"LightCyan\"
 file_fg = \"LightWhite\"
 symlink_fg = \"LightYellow\"
+
+# (optional) keep the trash small. Items are deleted permanently at startup and by `:purge`.
+# max_days: delete items trashed more than this number of days ago
+# max_size: keep the total size of the trash under this size (MB), deleting oldest items first
+# [trash]
+# max_days = 30
+# max_size = 10000
+
+# (optional) how to copy items when putting.
+# preserve: keep mode and timestamps, and copy symlinks as symlinks (default: true)
+# xattrs: also copy extended attributes (default: false)
+# [copy]
+# preserve = true
+# xattrs = false
+
+# (optional) how sizes and times are shown.
+# size_unit: \"SI\" (1KB = 1000B) or \"Binary\" (1KiB = 1024B) (default: \"SI\")
+# size_precision: number of decimal places (default: 1)
+# time: strftime-style format, or \"relative\" such as \"3h ago\" (default: \"%Y-%m-%d %H:%M\")
+# [format]
+# size_unit = \"SI\"
+# size_precision = 1
+# time = \"%Y-%m-%d %H:%M\"
+
+# (optional) columns shown after the name, from left to right.
+# If the terminal is too narrow, columns are hidden from the right.
+# Default: the modified time only.
+# kind: \"Size\", \"Permissions\", \"Owner\", \"Group\", \"Modified\", \"Changed\" (status change time),
+#       \"Items\" (number of items in the directory) or \"LinkTarget\"
+# width: (optional) width of the column
+# align: (optional) \"Left\" or \"Right\"
+# [[columns]]
+# kind = \"Modified\"
+# [[columns]]
+# kind = \"Permissions\"
+# [[columns]]
+# kind = \"Size\"
+# width = 8
+# align = \"Right\"
 ";
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     pub default: String,
     pub sort_by: SortKey,
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
+    #[serde(default)]
+    pub open_new_file: bool,
+    pub author: Option<String>,
     pub exec: HashMap<String, Vec<String>>,
     pub color: Color,
+    #[serde(default)]
+    pub trash: TrashConfig,
+    #[serde(default)]
+    pub copy: CopyConfig,
+    #[serde(default)]
+    pub format: FormatConfig,
+    #[serde(default = "default_columns")]
+    pub columns: Vec<Column>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub enum SortKey {
     Name,
     Time,
+    Size,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -66,6 +132,120 @@ pub struct Color {
     pub symlink_fg: Colorname,
 }
 
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    #[default]
+    Ask,
+    Rename,
+    Overwrite,
+    Skip,
+}
+
+//Column shown after the name in the item list.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Column {
+    pub kind: ColumnKind,
+    pub width: Option<u16>,
+    pub align: Option<Align>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKind {
+    Size,
+    Permissions,
+    Owner,
+    Group,
+    Modified,
+    Changed,
+    Items,
+    LinkTarget,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+impl Column {
+    fn new(kind: ColumnKind) -> Self {
+        Column {
+            kind,
+            width: None,
+            align: None,
+        }
+    }
+
+    //Sizes and times are as wide as they are formatted.
+    pub fn width(&self, format: &FormatConfig) -> u16 {
+        self.width.unwrap_or(match self.kind {
+            ColumnKind::Size => size_width(format) as u16,
+            ColumnKind::Permissions => 9,
+            ColumnKind::Owner | ColumnKind::Group => 8,
+            ColumnKind::Modified | ColumnKind::Changed => time_width(format) as u16,
+            ColumnKind::Items => 5,
+            ColumnKind::LinkTarget => 24,
+        })
+    }
+
+    pub fn align(&self) -> Align {
+        self.align.unwrap_or(match self.kind {
+            ColumnKind::Size | ColumnKind::Items => Align::Right,
+            _ => Align::Left,
+        })
+    }
+}
+
+fn default_columns() -> Vec<Column> {
+    vec![Column::new(ColumnKind::Modified)]
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct TrashConfig {
+    pub max_days: Option<u64>,
+    pub max_size: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CopyConfig {
+    pub preserve: bool,
+    pub xattrs: bool,
+}
+
+impl Default for CopyConfig {
+    fn default() -> Self {
+        CopyConfig {
+            preserve: true,
+            xattrs: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FormatConfig {
+    pub size_unit: SizeUnit,
+    pub size_precision: usize,
+    pub time: String,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        FormatConfig {
+            size_unit: SizeUnit::SI,
+            size_precision: 1,
+            time: DEFAULT_TIME_FORMAT.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeUnit {
+    SI,
+    Binary,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub enum Colorname {
     AnsiValue(u8),
@@ -0,0 +1,129 @@
+use super::functions::count_size;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+//Recursive sizes of directories, computed one by one on a worker thread and cached by path.
+#[derive(Debug, Clone, Default)]
+pub struct DirSizes {
+    shared: Arc<Mutex<Shared>>,
+    //Increased whenever a size arrives, so that the list is redrawn only when needed.
+    generation: Arc<AtomicUsize>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    cache: HashMap<PathBuf, u64>,
+    queue: VecDeque<PathBuf>,
+    running: bool,
+    //Directory whose size is being computed, and whether it has been invalidated since then,
+    //in which case the size is dropped.
+    current: Option<PathBuf>,
+    stale: bool,
+}
+
+impl DirSizes {
+    pub fn get(&self, path: &Path) -> Option<u64> {
+        self.shared.lock().unwrap().cache.get(path).copied()
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.shared.lock().unwrap().running
+    }
+
+    pub fn generation(&self) -> usize {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    //Compute sizes of the directories not cached yet, or all of them if force is true.
+    //Directories already waiting are not added again, and the worker is started only if not running.
+    pub fn request(&self, dirs: Vec<PathBuf>, force: bool) {
+        let mut shared = self.shared.lock().unwrap();
+        for dir in dirs {
+            if (force || !shared.cache.contains_key(&dir)) && !shared.queue.contains(&dir) {
+                shared.queue.push_back(dir);
+            }
+        }
+        if shared.running || shared.queue.is_empty() {
+            return;
+        }
+        shared.running = true;
+        let sizes = self.clone();
+        std::thread::spawn(move || sizes.work());
+    }
+
+    fn work(&self) {
+        loop {
+            let dir = {
+                let mut shared = self.shared.lock().unwrap();
+                match shared.queue.pop_front() {
+                    Some(dir) => {
+                        shared.current = Some(dir.clone());
+                        shared.stale = false;
+                        dir
+                    }
+                    None => {
+                        shared.running = false;
+                        return;
+                    }
+                }
+            };
+            let (bytes, _) = count_size(&dir);
+            let mut shared = self.shared.lock().unwrap();
+            shared.current = None;
+            if !shared.stale {
+                shared.cache.insert(dir, bytes);
+                //generation first, so that it is up to date once the worker stops
+                self.generation.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    //Forget the sizes of the changed path, the directories in it and those above it.
+    pub fn invalidate(&self, path: &Path) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.cache.retain(|dir, _| !is_affected(dir, path));
+        if shared
+            .current
+            .as_ref()
+            .is_some_and(|dir| is_affected(dir, path))
+        {
+            shared.stale = true;
+        }
+    }
+}
+
+fn is_affected(dir: &Path, changed: &Path) -> bool {
+    dir.starts_with(changed) || changed.starts_with(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testdir::TestDir;
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_invalidate_keeps_other_sizes() {
+        let temp = TestDir::new();
+        let a = temp.path().join("a");
+        let b = temp.path().join("b");
+        fs::create_dir_all(a.join("sub")).unwrap();
+        fs::create_dir(&b).unwrap();
+        fs::write(a.join("sub/file"), "abc").unwrap();
+        fs::write(b.join("file"), "abcde").unwrap();
+
+        let sizes = DirSizes::default();
+        sizes.request(vec![a.clone(), b.clone()], false);
+        while sizes.is_busy() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(sizes.get(&a), Some(3));
+        assert_eq!(sizes.get(&b), Some(5));
+
+        sizes.invalidate(&a.join("sub/file"));
+        assert_eq!(sizes.get(&a), None);
+        assert_eq!(sizes.get(&b), Some(5));
+    }
+}
@@ -1,32 +1,201 @@
-use super::config::CONFIG_EXAMPLE;
+use super::config::*;
+use super::progress::Progress;
 use super::state::*;
-use log::{debug, error};
+use super::trash::make_trash;
+use chrono::prelude::*;
+use filetime::FileTime;
+use log::debug;
+use regex::bytes::Regex;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write as _;
 use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
 use std::path::{Path, PathBuf};
 use termion::{clear, color, cursor, style};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 pub fn make_config(config_file: &Path, trash_dir: &Path) -> std::io::Result<()> {
-    if !trash_dir.exists() {
-        fs::create_dir_all(trash_dir)?;
-    }
+    make_trash(trash_dir)?;
 
     if !config_file.exists() {
-        fs::write(&config_file, CONFIG_EXAMPLE)
+        fs::write(config_file, CONFIG_EXAMPLE)
             .unwrap_or_else(|_| panic!("cannot write new config file."));
     }
 
     Ok(())
 }
 
-pub fn format_time(time: &Option<String>) -> String {
-    match time {
-        Some(datetime) => format!("{} {}", &datetime[0..10], &datetime[11..16]),
+//time is in RFC3339, as in ItemInfo.
+pub fn format_time(time: &Option<String>, format: &FormatConfig) -> String {
+    match time
+        .as_ref()
+        .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+    {
+        Some(time) => format_date(time.with_timezone(&Local), format),
         None => "".to_string(),
     }
 }
 
+//Invalid strftime-style formats fall back to the default one.
+pub fn format_date(time: DateTime<Local>, format: &FormatConfig) -> String {
+    if format.time == RELATIVE_TIME {
+        return format_relative(Local::now().signed_duration_since(time).num_seconds());
+    }
+    let mut result = String::new();
+    if write!(result, "{}", time.format(&format.time)).is_err() {
+        result = time.format(DEFAULT_TIME_FORMAT).to_string();
+    }
+    result
+}
+
+//e.g. `now`, `5m ago`, `3h ago`, `2d ago`, `4mo ago`, `1y ago`
+fn format_relative(seconds: i64) -> String {
+    const MINUTE: i64 = 60;
+    const HOUR: i64 = 60 * MINUTE;
+    const DAY: i64 = 24 * HOUR;
+    const MONTH: i64 = 30 * DAY;
+    const YEAR: i64 = 365 * DAY;
+    match seconds {
+        s if s < MINUTE => "now".to_string(),
+        s if s < HOUR => format!("{}m ago", s / MINUTE),
+        s if s < DAY => format!("{}h ago", s / HOUR),
+        s if s < MONTH => format!("{}d ago", s / DAY),
+        s if s < YEAR => format!("{}mo ago", s / MONTH),
+        s => format!("{}y ago", s / YEAR),
+    }
+}
+
+//Width of the longest time in the format, such as `12mo ago` or `Wednesday, September 27`.
+pub fn time_width(format: &FormatConfig) -> usize {
+    if format.time == RELATIVE_TIME {
+        return 8;
+    }
+    let sample = NaiveDate::from_ymd(2000, 9, 27).and_hms(23, 59, 59);
+    match Local.from_local_datetime(&sample).single() {
+        Some(time) => format_date(time, format).width(),
+        None => DEFAULT_TIME_FORMAT.len(),
+    }
+}
+
+//Name or path shown on the screen. Bytes that are not valid UTF-8 are shown as `\xff`,
+//and control characters, which could move the cursor or send commands to the terminal, as `\x1b`.
+pub fn escape_name(name: &OsStr) -> String {
+    let mut result = String::new();
+    for chunk in name.as_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c.is_control() {
+                result.push_str(&format!("\\x{:02x}", c as u32));
+            } else if is_bidi_control(c) {
+                result.push_str(&format!("\\u{{{:04x}}}", c as u32));
+            } else {
+                result.push(c);
+            }
+        }
+        for byte in chunk.invalid() {
+            result.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    result
+}
+
+//Text edited in place, where each character must stay one character: control characters become `?`.
+pub fn replace_control(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_control() || is_bidi_control(c) {
+                '?'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+//Characters that reorder the text around them, so that e.g. `txt.exe` looks like `exe.txt`.
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+}
+
+//Cut the end of the text so that it fits in the display width.
+//Wide characters (CJK, emoji) take 2 columns, and combining characters are kept with their base.
+pub fn truncate_to_width(text: &str, width: usize) -> String {
+    let mut result = String::new();
+    let mut result_width = 0;
+    for grapheme in text.graphemes(true) {
+        result_width += grapheme.width();
+        if result_width > width {
+            break;
+        }
+        result.push_str(grapheme);
+    }
+    result
+}
+
+//Pad the text with spaces up to the display width.
+pub fn pad_to_width(text: &str, width: usize) -> String {
+    format!("{}{}", text, " ".repeat(width.saturating_sub(text.width())))
+}
+
+//Shorten the name to the display width by cutting out its middle,
+//so that the extension stays visible: e.g. `very_long_na…me.tar.gz`
+pub fn shorten_name(name: &str, width: usize) -> String {
+    if name.width() <= width {
+        return name.to_string();
+    }
+    if width == 0 {
+        return "".to_string();
+    }
+    //1 for the ellipsis
+    let available = width - 1;
+    let ext_width = extension_of(name).map_or(0, |ext| ext.width());
+    let tail_width = if ext_width <= available / 2 {
+        ext_width.max(available / 3)
+    } else {
+        available / 3
+    };
+
+    let graphemes: Vec<&str> = name.graphemes(true).collect();
+    let mut tail = Vec::new();
+    let mut current = 0;
+    for grapheme in graphemes.iter().rev() {
+        if current + grapheme.width() > tail_width {
+            break;
+        }
+        current += grapheme.width();
+        tail.push(*grapheme);
+    }
+    tail.reverse();
+
+    let mut result = truncate_to_width(name, available - current);
+    result.push('…');
+    result.push_str(&tail.concat());
+    result
+}
+
+//`.tar.gz` of `archive.tar.gz`. Dotfiles such as `.bashrc` have no extension.
+fn extension_of(name: &str) -> Option<&str> {
+    let start = name.char_indices().skip(1).find(|(_, c)| *c == '.')?.0;
+    Some(&name[start..])
+}
+
+//e.g. `rwxr-xr-x`
+pub fn format_permissions(mode: u32) -> String {
+    let mut result = String::new();
+    for (i, c) in "rwxrwxrwx".chars().enumerate() {
+        if mode & (0o400 >> i) != 0 {
+            result.push(c);
+        } else {
+            result.push('-');
+        }
+    }
+    result
+}
+
 pub fn clear_and_show(dir: &Path) {
     print!("{}{}", clear::All, cursor::Goto(1, 1));
     debug!("clear::All finished.");
@@ -35,7 +204,7 @@ pub fn clear_and_show(dir: &Path) {
         " {}{}{}{}{}",
         style::Bold,
         color::Fg(color::Cyan),
-        dir.display(),
+        escape_name(dir.as_os_str()),
         style::Reset,
         color::Fg(color::Reset),
     );
@@ -45,7 +214,7 @@ pub fn clear_and_show(dir: &Path) {
     if git.exists() {
         let head = std::fs::read(".git/HEAD").unwrap();
         let branch: Vec<u8> = head.into_iter().skip(16).collect();
-        let branch = std::str::from_utf8(&branch).unwrap();
+        let branch = escape_name(OsStr::from_bytes(branch.trim_ascii_end()));
         debug!("caught current_branch.");
         debug!("current branch to String finished.");
         print!(
@@ -63,7 +232,7 @@ pub fn clear_and_show(dir: &Path) {
     debug!("arrow appeared.");
 }
 
-pub fn rename_file(item: &ItemInfo, name_set: &HashSet<String>) -> String {
+pub fn rename_file(item: &ItemInfo, name_set: &HashSet<OsString>) -> OsString {
     let file_name = &item.file_name;
     if name_set.contains(file_name) {
         let rename = PathBuf::from(&(item).file_name);
@@ -77,10 +246,6 @@ pub fn rename_file(item: &ItemInfo, name_set: &HashSet<String>) -> String {
             rename.push("_copied");
         }
 
-        let rename = rename
-            .into_string()
-            .unwrap_or_else(|_| panic!("cannot rename item."));
-
         let mut renamed_item = item.clone();
         renamed_item.file_name = rename;
         rename_file(&renamed_item, name_set)
@@ -89,11 +254,11 @@ pub fn rename_file(item: &ItemInfo, name_set: &HashSet<String>) -> String {
     }
 }
 
-pub fn rename_dir(item: &ItemInfo, name_set: &HashSet<String>) -> String {
+pub fn rename_dir(item: &ItemInfo, name_set: &HashSet<OsString>) -> OsString {
     let dir_name = &item.file_name;
     if name_set.contains(dir_name) {
         let mut rename = dir_name.clone();
-        rename.push_str("_copied");
+        rename.push("_copied");
         let mut renamed_item = item.clone();
         renamed_item.file_name = rename;
         rename_dir(&renamed_item, name_set)
@@ -102,6 +267,37 @@ pub fn rename_dir(item: &ItemInfo, name_set: &HashSet<String>) -> String {
     }
 }
 
+//Path of to as seen from the directory from, e.g. `../dir/file`.
+pub fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<_> = from.components().collect();
+    let to: Vec<_> = to.components().collect();
+    let common = from
+        .iter()
+        .zip(to.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut result = PathBuf::new();
+    for _ in common..from.len() {
+        result.push("..");
+    }
+    for component in to.iter().skip(common) {
+        result.push(component);
+    }
+    result
+}
+
+pub fn make_link(from: &Path, to: &Path, kind: LinkKind) -> std::io::Result<()> {
+    match kind {
+        LinkKind::Symlink => std::os::unix::fs::symlink(from, to),
+        LinkKind::RelativeSymlink => {
+            let dir = to.parent().unwrap_or_else(|| Path::new("/"));
+            std::os::unix::fs::symlink(relative_path(dir, from), to)
+        }
+        LinkKind::HardLink => fs::hard_link(from, to),
+    }
+}
+
 pub fn print_warning<T: std::fmt::Display>(message: T, then: u16) {
     print!(
         " {}{}{}{}{}{}{}",
@@ -140,6 +336,29 @@ pub fn print_info<T: std::fmt::Display>(message: T, then: u16) {
     );
 }
 
+pub fn to_fg(color: &Colorname) -> String {
+    match color {
+        Colorname::AnsiValue(n) => color::Fg(color::AnsiValue(*n)).to_string(),
+        Colorname::Black => color::Fg(color::Black).to_string(),
+        Colorname::Blue => color::Fg(color::Blue).to_string(),
+        Colorname::Cyan => color::Fg(color::Cyan).to_string(),
+        Colorname::Green => color::Fg(color::Green).to_string(),
+        Colorname::LightBlack => color::Fg(color::LightBlack).to_string(),
+        Colorname::LightBlue => color::Fg(color::LightBlue).to_string(),
+        Colorname::LightCyan => color::Fg(color::LightCyan).to_string(),
+        Colorname::LightGreen => color::Fg(color::LightGreen).to_string(),
+        Colorname::LightMagenta => color::Fg(color::LightMagenta).to_string(),
+        Colorname::LightRed => color::Fg(color::LightRed).to_string(),
+        Colorname::LightWhite => color::Fg(color::LightWhite).to_string(),
+        Colorname::LightYellow => color::Fg(color::LightYellow).to_string(),
+        Colorname::Magenta => color::Fg(color::Magenta).to_string(),
+        Colorname::Red => color::Fg(color::Red).to_string(),
+        Colorname::Rgb(x, y, z) => color::Fg(color::Rgb(*x, *y, *z)).to_string(),
+        Colorname::White => color::Fg(color::White).to_string(),
+        Colorname::Yellow => color::Fg(color::Yellow).to_string(),
+    }
+}
+
 pub fn to_extension_map(config: &HashMap<String, Vec<String>>) -> HashMap<String, String> {
     let mut new_map = HashMap::new();
     for (command, extensions) in config.iter() {
@@ -150,20 +369,594 @@ pub fn to_extension_map(config: &HashMap<String, Vec<String>>) -> HashMap<String
     new_map
 }
 
-pub fn to_proper_size(byte: u64) -> String {
-    let mut result: String;
-    if byte < 1000 {
-        result = byte.to_string();
-        result.push('B');
-    } else if byte < 1_000_000 {
-        result = (byte / 1_000).to_string();
-        result.push_str("KB");
-    } else if byte < 1_000_000_000 {
-        result = (byte / 1_000_000).to_string();
-        result.push_str("MB");
+//e.g. `999B`, `1.9KB` or `1.9KiB`
+pub fn format_size(byte: u64, format: &FormatConfig) -> String {
+    let (base, units) = match format.size_unit {
+        SizeUnit::SI => (1000.0, ["KB", "MB", "GB", "TB", "PB"]),
+        SizeUnit::Binary => (1024.0, ["KiB", "MiB", "GiB", "TiB", "PiB"]),
+    };
+    if (byte as f64) < base {
+        return format!("{}B", byte);
+    }
+
+    let precision = format.size_precision;
+    let scale = 10_f64.powi(precision as i32);
+    let mut value = byte as f64;
+    let mut unit = 0;
+    loop {
+        value /= base;
+        //e.g. 999_999 bytes is 1.0MB, not 1000.0KB
+        if (value * scale).round() / scale < base || unit == units.len() - 1 {
+            break;
+        }
+        unit += 1;
+    }
+    format!("{:.*}{}", precision, value, units[unit])
+}
+
+pub fn size_width(format: &FormatConfig) -> usize {
+    let (digits, unit) = match format.size_unit {
+        SizeUnit::SI => (3, 2),
+        SizeUnit::Binary => (4, 3),
+    };
+    let decimals = match format.size_precision {
+        0 => 0,
+        precision => precision + 1,
+    };
+    digits + decimals + unit
+}
+
+//Move item with rename, falling back to copy and remove across filesystems.
+pub fn move_item(from: &Path, to: &Path, progress: &Progress) -> std::io::Result<()> {
+    match fs::rename(from, to) {
+        Ok(_) => Ok(()),
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            //moved items should look the same as before
+            let options = CopyConfig {
+                preserve: true,
+                xattrs: true,
+            };
+            copy_item(from, to, progress, &options)?;
+            if fs::symlink_metadata(from)?.is_dir() {
+                fs::remove_dir_all(from)
+            } else {
+                fs::remove_file(from)
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+//Copy file or directory, reporting to progress.
+//Totals are added by the caller for all items at once, before the first one is copied.
+//If it fails or is cancelled, the partial copy is removed, unless to existed before.
+pub fn copy_item(
+    from: &Path,
+    to: &Path,
+    progress: &Progress,
+    options: &CopyConfig,
+) -> std::io::Result<()> {
+    let existed = fs::symlink_metadata(to).is_ok();
+    if let Err(e) = copy_all(from, to, progress, options) {
+        if existed {
+            return Err(e);
+        }
+        if fs::symlink_metadata(to).is_ok_and(|metadata| metadata.is_dir()) {
+            let _ = fs::remove_dir_all(to);
+        } else {
+            let _ = fs::remove_file(to);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn copy_all(
+    from: &Path,
+    to: &Path,
+    progress: &Progress,
+    options: &CopyConfig,
+) -> std::io::Result<()> {
+    if !fs::symlink_metadata(from)?.is_dir() {
+        return copy_entry(from, to, progress, options);
+    }
+
+    let base = from.iter().count();
+    let mut dirs = Vec::new();
+    for entry in walkdir::WalkDir::new(from).sort_by_key(|x| x.path().to_path_buf()) {
+        let entry = entry?;
+        let child: PathBuf = entry.path().iter().skip(base).collect();
+        let target = to.join(child);
+        if entry.file_type().is_dir() {
+            fs::create_dir(&target)?;
+            dirs.push((entry.path().to_path_buf(), target));
+        } else {
+            copy_entry(entry.path(), &target, progress, options)?;
+        }
+    }
+
+    //directories are set last, as copying their contents changes mtime
+    //and a read-only directory cannot be written into
+    if options.preserve {
+        for (source, target) in dirs.iter().rev() {
+            copy_metadata(source, target, options)?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_entry(
+    from: &Path,
+    to: &Path,
+    progress: &Progress,
+    options: &CopyConfig,
+) -> std::io::Result<()> {
+    if options.preserve && fs::symlink_metadata(from)?.file_type().is_symlink() {
+        progress.check()?;
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)?;
+        copy_metadata(from, to, options)?;
+        progress.add_file();
+        return Ok(());
+    }
+
+    copy_file(from, to, progress)?;
+    if options.preserve {
+        copy_metadata(from, to, options)
     } else {
-        result = (byte / 1_000_000_000).to_string();
-        result.push_str("GB");
+        fs::set_permissions(to, fs::metadata(from)?.permissions())
+    }
+}
+
+//Copy file in chunks like fs::copy, so that progress is shown and it can be cancelled.
+fn copy_file(from: &Path, to: &Path, progress: &Progress) -> std::io::Result<()> {
+    progress.check()?;
+    let mut reader = fs::File::open(from)?;
+    let mut writer = fs::File::create(to)?;
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..len])?;
+        progress.add_bytes(len as u64);
+        progress.check()?;
+    }
+    progress.add_file();
+    Ok(())
+}
+
+//Set xattrs (optional), mode and atime/mtime of to as those of from.
+//xattrs go first, as a read-only file does not accept them.
+//Symlinks themselves have no mode, so only the times are set.
+fn copy_metadata(from: &Path, to: &Path, options: &CopyConfig) -> std::io::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    if options.xattrs {
+        for name in xattr::list(from)? {
+            if let Some(value) = xattr::get(from, &name)? {
+                //some attributes (e.g. security.*) cannot be set by normal users
+                if let Err(e) = xattr::set(to, &name, &value) {
+                    debug!("cannot copy xattr {:?}: {}", name, e);
+                }
+            }
+        }
+    }
+    if !metadata.file_type().is_symlink() {
+        fs::set_permissions(to, metadata.permissions())?;
+    }
+    filetime::set_symlink_file_times(
+        to,
+        FileTime::from_last_access_time(&metadata),
+        FileTime::from_last_modification_time(&metadata),
+    )
+}
+
+pub fn count_size(path: &Path) -> (u64, u64) {
+    let mut bytes = 0;
+    let mut files = 0;
+    for entry in walkdir::WalkDir::new(path).into_iter().flatten() {
+        if !entry.file_type().is_dir() {
+            if let Ok(metadata) = entry.metadata() {
+                bytes += metadata.len();
+            }
+            files += 1;
+        }
+    }
+    (bytes, files)
+}
+
+//Bytes and files copied to move the item into the directory,
+//which are none on the same filesystem, where it is just renamed.
+pub fn count_move(from: &Path, to_dir: &Path) -> (u64, u64) {
+    match (fs::symlink_metadata(from), fs::metadata(to_dir)) {
+        (Ok(from_metadata), Ok(to_metadata)) if from_metadata.dev() != to_metadata.dev() => {
+            count_size(from)
+        }
+        _ => (0, 0),
+    }
+}
+
+pub fn make_name_set(dir: &Path) -> std::io::Result<HashSet<OsString>> {
+    let mut name_set = HashSet::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        name_set.insert(entry.file_name());
+    }
+    Ok(name_set)
+}
+
+//Size and modified time, to compare items.
+pub fn describe_item(path: &Path, format: &FormatConfig) -> String {
+    let (bytes, _) = count_size(path);
+    let time = fs::symlink_metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map(|time| format_date(DateTime::<Local>::from(time), format))
+        .unwrap_or_default();
+    format!("{} {}", format_size(bytes, format), time)
+}
+
+//Write names to a temporary file, one per line, let the user edit it, and read them back.
+pub fn edit_names(editor: &str, names: &[String]) -> std::io::Result<Vec<String>> {
+    if names.iter().any(|name| name.contains('\n')) {
+        return Err(std::io::Error::other("cannot edit names with newline."));
+    }
+    //private directory, so that no one else can replace the file while it is edited
+    let dir = std::env::temp_dir().join(format!(
+        "felix_rename_{}_{}",
+        std::process::id(),
+        Local::now().timestamp_nanos()
+    ));
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let path = dir.join("names");
+    let result = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut file| file.write_all((names.join("\n") + "\n").as_bytes()));
+    if let Err(e) = result {
+        let _ = fs::remove_dir_all(&dir);
+        return Err(e);
+    }
+
+    let status = open_in_editor(editor, &path);
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_dir_all(&dir);
+    if !status?.success() {
+        return Err(std::io::Error::other("editor exited with error."));
+    }
+
+    let edited: Vec<String> = edited?.lines().map(|line| line.to_string()).collect();
+    if edited.len() != names.len() {
+        return Err(std::io::Error::other("number of lines changed."));
+    }
+    Ok(edited)
+}
+
+//Run the editor on the file. $EDITOR may contain arguments, so it goes through the shell.
+pub fn open_in_editor(editor: &str, path: &Path) -> std::io::Result<std::process::ExitStatus> {
+    std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path)
+        .status()
+}
+
+//New names from `s/regex/replacement/` (`g` at the end to replace all matches, `\/` for `/` in them),
+//or from a template such as `photo_{n:03}.{ext}`,
+//where {n} is the number from 1, {name} the name without extension and {ext} the extension.
+//Names are matched as bytes, so that those not in UTF-8 are kept as they are.
+pub fn apply_pattern(pattern: &str, names: &[OsString]) -> std::io::Result<Vec<OsString>> {
+    if let Some(rest) = pattern.strip_prefix("s/") {
+        let parts = split_pattern(rest);
+        if parts.len() != 3 || (!parts[2].is_empty() && parts[2] != "g") {
+            return Err(std::io::Error::other("usage: s/regex/replacement/[g]"));
+        }
+        let re = Regex::new(&parts[0]).map_err(|e| std::io::Error::other(e.to_string()))?;
+        let replacement = parts[1].as_bytes();
+        Ok(names
+            .iter()
+            .map(|name| {
+                let replaced = if parts[2] == "g" {
+                    re.replace_all(name.as_bytes(), replacement)
+                } else {
+                    re.replace(name.as_bytes(), replacement)
+                };
+                OsString::from_vec(replaced.into_owned())
+            })
+            .collect())
+    } else {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| fill_template(pattern, i + 1, name))
+            .collect()
+    }
+}
+
+//Split `regex/replacement/flags` at `/` not escaped with `\`.
+fn split_pattern(pattern: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('/') => part.push('/'),
+                Some(next) => {
+                    part.push(c);
+                    part.push(next);
+                }
+                None => part.push(c),
+            },
+            '/' if parts.len() < 2 => parts.push(std::mem::take(&mut part)),
+            _ => part.push(c),
+        }
+    }
+    parts.push(part);
+    parts
+}
+
+fn fill_template(template: &str, n: usize, name: &OsStr) -> std::io::Result<OsString> {
+    let path = Path::new(name);
+    let stem = path.file_stem().unwrap_or_default();
+    let ext = path.extension().unwrap_or_default();
+
+    let mut result: Vec<u8> = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.extend_from_slice(&rest.as_bytes()[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| std::io::Error::other("unclosed {."))?
+            + start;
+        let variable = &rest[start + 1..end];
+        let (key, width) = match variable.split_once(':') {
+            Some((key, width)) => (key, Some(width)),
+            None => (variable, None),
+        };
+        match key {
+            "n" => {
+                let width = match width {
+                    Some(width) => width
+                        .parse::<usize>()
+                        .map_err(|_| std::io::Error::other("invalid width of {n}."))?,
+                    None => 0,
+                };
+                result.extend_from_slice(format!("{:0width$}", n, width = width).as_bytes());
+            }
+            "name" => result.extend_from_slice(stem.as_bytes()),
+            //`{name}.{ext}` for items without extension gives just the name
+            "ext" if ext.is_empty() => {
+                if result.ends_with(b".") {
+                    result.pop();
+                }
+            }
+            "ext" => result.extend_from_slice(ext.as_bytes()),
+            _ => {
+                return Err(std::io::Error::other(format!(
+                    "unknown variable: {{{}}}",
+                    key
+                )))
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    result.extend_from_slice(rest.as_bytes());
+    Ok(OsString::from_vec(result))
+}
+
+//Replace `{{key}}` in the text with the value.
+pub fn fill_variables(text: &str, variables: &[(&str, String)]) -> String {
+    let mut result = text.to_string();
+    for (key, value) in variables.iter() {
+        result = result.replace(&format!("{{{{{}}}}}", key), value);
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<OsString> {
+        names.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_apply_regex() {
+        let from = names(&["a_a.txt", "b.txt"]);
+        assert_eq!(
+            apply_pattern("s/a/x/", &from).unwrap(),
+            names(&["x_a.txt", "b.txt"])
+        );
+        assert_eq!(
+            apply_pattern("s/a/x/g", &from).unwrap(),
+            names(&["x_x.txt", "b.txt"])
+        );
+        assert_eq!(
+            apply_pattern(r"s/(\w+)\.txt/${1}.md/", &from).unwrap(),
+            names(&["a_a.md", "b.md"])
+        );
+        assert!(apply_pattern("s/a/x", &from).is_err());
+        assert!(apply_pattern("s/a/x/i", &from).is_err());
+        assert!(apply_pattern("s/(/x/", &from).is_err());
+    }
+
+    #[test]
+    fn test_apply_regex_with_slash() {
+        let from = names(&["a-b"]);
+        assert_eq!(apply_pattern(r"s/-/\//", &from).unwrap(), names(&["a/b"]));
+        let from = names(&["a/b"]);
+        assert_eq!(apply_pattern(r"s/a\/b/c/", &from).unwrap(), names(&["c"]));
+        //other escapes are left to the regex
+        let from = names(&["a.b"]);
+        assert_eq!(apply_pattern(r"s/\./_/", &from).unwrap(), names(&["a_b"]));
+    }
+
+    #[test]
+    fn test_apply_regex_to_non_utf8() {
+        let from = vec![OsString::from_vec(b"a\xffb".to_vec())];
+        assert_eq!(
+            apply_pattern("s/b/c/", &from).unwrap(),
+            vec![OsString::from_vec(b"a\xffc".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_fill_template() {
+        let from = names(&["photo.jpg", "archive.tar.gz", "README", ".bashrc"]);
+        assert_eq!(
+            apply_pattern("{n:03}_{name}.{ext}", &from).unwrap(),
+            names(&[
+                "001_photo.jpg",
+                "002_archive.tar.gz",
+                "003_README",
+                "004_.bashrc"
+            ])
+        );
+        assert_eq!(
+            apply_pattern("{name}.{ext}.bak", &from).unwrap(),
+            names(&[
+                "photo.jpg.bak",
+                "archive.tar.gz.bak",
+                "README.bak",
+                ".bashrc.bak"
+            ])
+        );
+        assert_eq!(
+            apply_pattern("{n}.", &names(&["a"])).unwrap(),
+            names(&["1."])
+        );
+        assert!(apply_pattern("{n", &from).is_err());
+        assert!(apply_pattern("{m}", &from).is_err());
+        assert!(apply_pattern("{n:x}", &from).is_err());
+    }
+
+    #[test]
+    fn test_shorten_name() {
+        assert_eq!(shorten_name("short.txt", 10), "short.txt");
+        assert_eq!(shorten_name("short.txt", 0), "");
+        assert_eq!(
+            shorten_name("very_long_name_of_archive.tar.gz", 20),
+            "very_long_na….tar.gz"
+        );
+        //no extension: the last third is kept
+        assert_eq!(shorten_name("abcdefghijklmnopqrstuvwxyz", 10), "abcdef…xyz");
+        //dotfiles have no extension
+        assert_eq!(shorten_name(".very_long_dotfile", 10), ".very_…ile");
+        //extensions longer than half the width are cut as well
+        assert_eq!(shorten_name("a.very_long_extension", 10), "a.very…ion");
+    }
+
+    #[test]
+    fn test_shorten_name_by_width() {
+        //wide characters take 2 columns and are not split
+        assert_eq!(shorten_name("日本語のファイル名.txt", 12), "日本語….txt");
+        assert_eq!(shorten_name("日本語のファイル名.txt", 13), "日本語の….txt");
+        //combining characters stay with their base
+        let name = "e\u{301}e\u{301}e\u{301}e\u{301}e\u{301}e\u{301}e\u{301}e\u{301}";
+        let short = shorten_name(name, 5);
+        assert_eq!(short, "e\u{301}e\u{301}e\u{301}…e\u{301}");
+        assert_eq!(truncate_to_width("日本語", 5), "日本");
+        assert_eq!(pad_to_width("日本", 6), "日本  ");
+    }
+
+    #[test]
+    fn test_escape_name() {
+        assert_eq!(escape_name(OsStr::new("plain.txt")), "plain.txt");
+        assert_eq!(escape_name(OsStr::new("a\nb\x1b[31m")), "a\\x0ab\\x1b[31m");
+        assert_eq!(
+            escape_name(OsStr::new("invoice\u{202e}fdp.exe")),
+            "invoice\\u{202e}fdp.exe"
+        );
+        assert_eq!(
+            escape_name(OsStr::from_bytes(b"a\xff\xfeb")),
+            "a\\xff\\xfeb"
+        );
+        assert_eq!(escape_name(OsStr::new("日本語")), "日本語");
+        assert_eq!(replace_control("a\tb\u{202e}"), "a?b?");
+    }
+
+    fn size_format(size_unit: SizeUnit, size_precision: usize) -> FormatConfig {
+        FormatConfig {
+            size_unit,
+            size_precision,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_format_size() {
+        let si = size_format(SizeUnit::SI, 1);
+        assert_eq!(format_size(0, &si), "0B");
+        assert_eq!(format_size(999, &si), "999B");
+        assert_eq!(format_size(1000, &si), "1.0KB");
+        assert_eq!(format_size(1_500_000, &si), "1.5MB");
+        assert_eq!(format_size(u64::MAX, &si), "18446.7PB");
+
+        let binary = size_format(SizeUnit::Binary, 1);
+        assert_eq!(format_size(1023, &binary), "1023B");
+        assert_eq!(format_size(1024, &binary), "1.0KiB");
+        assert_eq!(format_size(1536, &binary), "1.5KiB");
+        assert_eq!(format_size(3 << 30, &binary), "3.0GiB");
+
+        assert_eq!(format_size(1234, &size_format(SizeUnit::SI, 0)), "1KB");
+        assert_eq!(format_size(1234, &size_format(SizeUnit::SI, 2)), "1.23KB");
+    }
+
+    #[test]
+    fn test_format_size_rounding_to_next_unit() {
+        let si = size_format(SizeUnit::SI, 1);
+        assert_eq!(format_size(999_949, &si), "999.9KB");
+        assert_eq!(format_size(999_950, &si), "1.0MB");
+        let si = size_format(SizeUnit::SI, 0);
+        assert_eq!(format_size(999_499, &si), "999KB");
+        assert_eq!(format_size(999_500, &si), "1MB");
+        let binary = size_format(SizeUnit::Binary, 1);
+        assert_eq!(format_size(1023 * 1024 + 900, &binary), "1023.9KiB");
+        assert_eq!(format_size(1024 * 1024 - 1, &binary), "1.0MiB");
+    }
+
+    #[test]
+    fn test_size_width() {
+        for (unit, precision) in [(SizeUnit::SI, 0), (SizeUnit::SI, 1), (SizeUnit::Binary, 2)] {
+            let format = size_format(unit, precision);
+            let base = if unit == SizeUnit::SI { 1000 } else { 1024 };
+            //e.g. 999.0KB, as wide as sizes get before the next unit
+            let widest = format_size(base * base - base - 1, &format);
+            assert!(widest.len() <= size_width(&format), "{}", widest);
+        }
+    }
+
+    #[test]
+    fn test_format_relative() {
+        const DAY: i64 = 24 * 60 * 60;
+        assert_eq!(format_relative(-5), "now");
+        assert_eq!(format_relative(59), "now");
+        assert_eq!(format_relative(60), "1m ago");
+        assert_eq!(format_relative(3599), "59m ago");
+        assert_eq!(format_relative(3600), "1h ago");
+        assert_eq!(format_relative(DAY - 1), "23h ago");
+        assert_eq!(format_relative(DAY), "1d ago");
+        assert_eq!(format_relative(30 * DAY - 1), "29d ago");
+        assert_eq!(format_relative(30 * DAY), "1mo ago");
+        assert_eq!(format_relative(365 * DAY - 1), "12mo ago");
+        assert_eq!(format_relative(365 * DAY), "1y ago");
+    }
+
+    #[test]
+    fn test_time_width() {
+        let format = |time: &str| FormatConfig {
+            time: time.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(time_width(&format(RELATIVE_TIME)), "12mo ago".len());
+        assert_eq!(time_width(&format("%Y-%m-%d")), 10);
+        //CJK characters take two columns each
+        assert_eq!(time_width(&format("%Y年%m月%d日")), 14);
+    }
+}
@@ -18,24 +18,56 @@ gg                    :Go to the top.
 G                     :Go to the bottom.
 dd                    :Delete and yank item.
 yy                    :Yank item.
+xx                    :Cut item.
 p                     :Put yanked item in the current directory.
+                       Cut items are moved instead of copied.
+                       On name conflict, choose overwrite/skip/rename/compare.
+Ps / Pr / Ph          :Make absolute/relative symlinks or hard links to yanked items.
+u                     :Undo delete, restore, rename, put, link, mkdir, new file or chmod.
+Ctrl + r              :Redo.
+m                     :Make new directory (with parents).
+n                     :Make new file.
+T                     :Make new file from template.
+M                     :Change permissions (h/l: move, Space: toggle, R: recursive, X: x for files).
 V                     :Switch to select mode.
   - d                 :In select mode, delete and yank selected items.
   - y                 :In select mode, yank selected items.
-t                     :Toggle sort order (name <-> modified time).
+  - x                 :In select mode, cut selected items.
+  - l / Enter         :In select mode, open selected files at once.
+  - c                 :In select mode, rename selected items in $EDITOR.
+  - :                 :In select mode, rename selected items by pattern.
+  - M                 :In select mode, change permissions of selected items.
+t                     :Toggle sort order (name -> modified time -> size).
+s                     :Compute recursive sizes of directories.
 :                     :Switch to shell mode.
 c                     :Switch to rename mode.
 /                     :Switch to filter mode.
 Esc                   :Return to normal mode.
+                       While putting or deleting, cancel it.
 :e                    :Reload the current directory.
+:bulkrename           :Rename all items in $EDITOR.
+:rename-pattern <pat> :Rename the item under the cursor by
+                       s/regex/replacement/[g] or template
+                       like photo_{n:03}.{ext} ({n}, {name}, {ext}).
+:restore              :Restore the item(s) deleted last time.
+                       In the trash directory, restore the item under the cursor.
+:trash                :Show items in the trash.
+  - r                 :In the trash, restore item.
+  - d                 :In the trash, delete item permanently.
+  - t                 :In the trash, toggle sort order (name -> time -> size).
+:purge                :Delete old items in the trash as configured.
 :empty                :Empty the trash directory.
+:du                   :Show items under the current directory by disk usage.
+  - l / h             :In disk usage, go into / back from directory.
+  - dd                :In disk usage, delete and yank item.
 :h                    :Show help.
 :q / ZZ               :Exit the program.
 
 ## Configuration
 
 config file    : $XDG_CONFIG_HOME/felix/config.toml
-trash directory: $XDG_CONFIG_HOME/felix/trash
+trash directory: $XDG_DATA_HOME/Trash
+                 ($topdir/.Trash-$uid on other filesystems)
 
 For more detail, visit:
 https://github.com/kyoheiu/felix
@@ -0,0 +1,100 @@
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use termion::event::{parse_event, Event, Key};
+
+//Bytes of escape sequences arrive together, so the rest of one is waited for only this long.
+const SEQUENCE_TIMEOUT: Duration = Duration::from_millis(10);
+
+//Keys read from stdin without std's buffer, so that waiting for input with poll sees every key not read yet.
+#[derive(Debug, Default)]
+pub struct Input {
+    buf: VecDeque<u8>,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    //Wait until a key is ready, at most timeout.
+    pub fn wait(&mut self, timeout: Duration) -> bool {
+        !self.buf.is_empty() || matches!(self.fill(Some(timeout)), Ok(Some(len)) if len > 0)
+    }
+
+    //Read what is ready, waiting at most timeout (or until input if None).
+    //Returns None if nothing is ready in time, and Some(0) at the end of input.
+    fn fill(&mut self, timeout: Option<Duration>) -> std::io::Result<Option<usize>> {
+        let mut fds = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.map_or(-1, |timeout| timeout.as_millis() as libc::c_int);
+        let ready = unsafe { libc::poll(&mut fds, 1, timeout) };
+        if ready < 0 {
+            let e = Error::last_os_error();
+            return match e.kind() {
+                ErrorKind::Interrupted => Ok(None),
+                _ => Err(e),
+            };
+        }
+        if ready == 0 {
+            return Ok(None);
+        }
+
+        let mut buf = [0u8; 1024];
+        let len = unsafe {
+            libc::read(
+                libc::STDIN_FILENO,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        };
+        if len < 0 {
+            let e = Error::last_os_error();
+            return match e.kind() {
+                ErrorKind::Interrupted | ErrorKind::WouldBlock => Ok(None),
+                _ => Err(e),
+            };
+        }
+        self.buf.extend(&buf[..len as usize]);
+        Ok(Some(len as usize))
+    }
+
+    //Next byte of a key, waiting a little for the rest of the sequence.
+    fn next_byte(&mut self) -> Option<std::io::Result<u8>> {
+        if self.buf.is_empty() {
+            if let Err(e) = self.fill(Some(SEQUENCE_TIMEOUT)) {
+                return Some(Err(e));
+            }
+        }
+        self.buf.pop_front().map(Ok)
+    }
+}
+
+impl Iterator for Input {
+    type Item = std::io::Result<Key>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            while self.buf.is_empty() {
+                match self.fill(None) {
+                    Ok(Some(0)) => return None,
+                    Ok(_) => {}
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            let first = self.buf.pop_front()?;
+            //a lone ESC is the Esc key, not the start of a sequence
+            if first == b'\x1B' && self.buf.is_empty() {
+                return Some(Ok(Key::Esc));
+            }
+            let mut rest = std::iter::from_fn(|| self.next_byte());
+            //mouse events and unknown sequences are skipped, as termion does
+            if let Ok(Event::Key(key)) = parse_event(first, &mut rest) {
+                return Some(Ok(key));
+            }
+        }
+    }
+}
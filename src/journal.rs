@@ -0,0 +1,911 @@
+use super::config::CopyConfig;
+use super::functions::*;
+use super::progress::Progress;
+use super::state::LinkKind;
+use super::trash;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+//Undo history is kept up to this number of entries.
+const MAX_ENTRIES: usize = 100;
+
+//What the item looked like right after the operation.
+//If it differs when undoing, the filesystem has changed underneath and undo is refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stamp {
+    len: u64,
+    modified: Option<SystemTime>,
+    is_dir: bool,
+    mode: u32,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Stamp> {
+        let metadata = fs::symlink_metadata(path).ok()?;
+        Some(Stamp {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            is_dir: metadata.is_dir(),
+            mode: metadata.permissions().mode(),
+        })
+    }
+}
+
+//Items as they will be after the operations checked so far,
+//so that an entry is checked as a whole before touching the filesystem.
+#[derive(Default)]
+struct Sandbox {
+    stamps: HashMap<PathBuf, Option<Stamp>>,
+}
+
+impl Sandbox {
+    fn stamp(&self, path: &Path) -> Option<Stamp> {
+        match self.stamps.get(path) {
+            Some(stamp) => stamp.clone(),
+            None => Stamp::of(path),
+        }
+    }
+
+    fn set(&mut self, path: &Path, stamp: Option<Stamp>) {
+        self.stamps.insert(path.to_path_buf(), stamp);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Operation {
+    //Renamed with `c`, or cut and put.
+    Move {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    //Deleted: to is the path in the trash.
+    Trash {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    //Restored from the trash: from is the path in the trash.
+    Restore {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    //Put as a copy of from.
+    Copy {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    //Put as a link to from.
+    Link {
+        from: PathBuf,
+        to: PathBuf,
+        kind: LinkKind,
+    },
+    //Made with mkdir, new file or template.
+    Create {
+        to: PathBuf,
+        is_dir: bool,
+        contents: Vec<u8>,
+        permissions: Option<fs::Permissions>,
+    },
+    //Permissions changed from old_mode to new_mode.
+    Chmod {
+        to: PathBuf,
+        old_mode: u32,
+        new_mode: u32,
+    },
+    //Removed after its contents were moved out, as when cut directories are merged.
+    RemoveDir {
+        to: PathBuf,
+        permissions: Option<fs::Permissions>,
+    },
+}
+
+impl Operation {
+    fn to(&self) -> &Path {
+        match self {
+            Operation::Move { to, .. }
+            | Operation::Trash { to, .. }
+            | Operation::Restore { to, .. }
+            | Operation::Copy { to, .. }
+            | Operation::Link { to, .. }
+            | Operation::Create { to, .. }
+            | Operation::Chmod { to, .. }
+            | Operation::RemoveDir { to, .. } => to,
+        }
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        match self {
+            Operation::Move { from, to }
+            | Operation::Trash { from, to }
+            | Operation::Restore { from, to }
+            | Operation::Copy { from, to }
+            | Operation::Link { from, to, .. } => vec![from.clone(), to.clone()],
+            Operation::Create { to, .. }
+            | Operation::Chmod { to, .. }
+            | Operation::RemoveDir { to, .. } => vec![to.clone()],
+        }
+    }
+
+    //Bytes and files copied when undoing or redoing, for progress.
+    fn count(&self, home_trash: &Path, undo: bool) -> (u64, u64) {
+        let parent = |path: &Path| path.parent().unwrap_or(path).to_path_buf();
+        match (self, undo) {
+            (Operation::Move { from, to }, true) | (Operation::Trash { from, to }, true) => {
+                count_move(to, &parent(from))
+            }
+            (Operation::Restore { to, .. }, true) | (Operation::Copy { to, .. }, true) => {
+                trash::count_trash(home_trash, to)
+            }
+            (Operation::Move { from, to }, false) | (Operation::Restore { from, to }, false) => {
+                count_move(from, &parent(to))
+            }
+            (Operation::Trash { from, .. }, false) => trash::count_trash(home_trash, from),
+            (Operation::Copy { from, .. }, false) => count_size(from),
+            _ => (0, 0),
+        }
+    }
+
+    //Copies, links and new items are removed by undo, so they are not stamped then.
+    fn stamp_undone(&self) -> Option<Stamp> {
+        match self {
+            Operation::Move { from, .. }
+            | Operation::Trash { from, .. }
+            | Operation::Restore { from, .. } => Stamp::of(from),
+            Operation::Copy { .. } | Operation::Link { .. } | Operation::Create { .. } => None,
+            Operation::Chmod { to, .. } | Operation::RemoveDir { to, .. } => Stamp::of(to),
+        }
+    }
+
+    fn check_undo(&self, stamp: &Option<Stamp>, sandbox: &mut Sandbox) -> std::io::Result<()> {
+        let to = self.to();
+        let unchanged = match self {
+            //making the next directory or file in it changes its modified time, so only the type is checked
+            Operation::Create { is_dir: true, .. } => {
+                sandbox.stamp(to).is_some_and(|stamp| stamp.is_dir)
+            }
+            _ => sandbox.stamp(to) == *stamp,
+        };
+        if !unchanged {
+            return Err(changed("undo", to));
+        }
+        match self {
+            Operation::Move { from, .. } | Operation::Trash { from, .. } => {
+                if sandbox.stamp(from).is_some() {
+                    return Err(changed("undo", from));
+                }
+                sandbox.set(from, stamp.clone());
+            }
+            //the path in the trash is decided when undone
+            Operation::Restore { .. }
+            | Operation::Copy { .. }
+            | Operation::Link { .. }
+            | Operation::Create { .. } => {}
+            Operation::Chmod { old_mode, .. } => {
+                let stamp = stamp.clone().map(|stamp| Stamp {
+                    mode: *old_mode,
+                    ..stamp
+                });
+                sandbox.set(to, stamp);
+                return Ok(());
+            }
+            //recreated empty, and filled by undoing the moves recorded before
+            Operation::RemoveDir { .. } => return Ok(()),
+        }
+        sandbox.set(to, None);
+        Ok(())
+    }
+
+    fn check_redo(&self, stamp: &Option<Stamp>, sandbox: &mut Sandbox) -> std::io::Result<()> {
+        match self {
+            Operation::Move { from, to } | Operation::Restore { from, to } => {
+                if sandbox.stamp(from) != *stamp {
+                    return Err(changed("redo", from));
+                }
+                if sandbox.stamp(to).is_some() {
+                    return Err(changed("redo", to));
+                }
+                sandbox.set(to, stamp.clone());
+                sandbox.set(from, None);
+            }
+            //the path in the trash is decided when redone
+            Operation::Trash { from, .. } => {
+                if sandbox.stamp(from) != *stamp {
+                    return Err(changed("redo", from));
+                }
+                sandbox.set(from, None);
+            }
+            Operation::Copy { from, to } | Operation::Link { from, to, .. } => {
+                let source = sandbox.stamp(from);
+                if source.is_none() {
+                    return Err(changed("redo", from));
+                }
+                if sandbox.stamp(to).is_some() {
+                    return Err(changed("redo", to));
+                }
+                sandbox.set(to, source);
+            }
+            Operation::Create { to, .. } => {
+                if sandbox.stamp(to).is_some() {
+                    return Err(changed("redo", to));
+                }
+                sandbox.set(to, stamp.clone());
+            }
+            Operation::Chmod { to, new_mode, .. } => {
+                if sandbox.stamp(to) != *stamp {
+                    return Err(changed("redo", to));
+                }
+                let stamp = stamp.clone().map(|stamp| Stamp {
+                    mode: *new_mode,
+                    ..stamp
+                });
+                sandbox.set(to, stamp);
+            }
+            //moving its contents out changes its modified time, so only the type is checked
+            Operation::RemoveDir { to, .. } => {
+                if !sandbox.stamp(to).is_some_and(|stamp| stamp.is_dir) {
+                    return Err(changed("redo", to));
+                }
+                sandbox.set(to, None);
+            }
+        }
+        Ok(())
+    }
+
+    fn undo(&mut self, home_trash: &Path, progress: &Progress) -> std::io::Result<()> {
+        match self {
+            Operation::Move { from, to } => move_item(to, from, progress),
+            Operation::Trash { from, to } => {
+                move_item(to, from, progress)?;
+                if let (Some(trash_dir), Some(name)) =
+                    (trash::trash_root(home_trash, to), to.file_name())
+                {
+                    let _ = trash::unregister(&trash_dir, name);
+                }
+                Ok(())
+            }
+            Operation::Restore { from, to } => {
+                *from = trash::move_to_trash(home_trash, to, progress)?;
+                Ok(())
+            }
+            //the copy may have been edited, so it goes to the trash instead of being removed
+            Operation::Copy { to, .. } => {
+                trash::move_to_trash(home_trash, to, progress).map(|_| ())
+            }
+            Operation::Link { to, .. } => fs::remove_file(to),
+            Operation::Create {
+                to, is_dir: true, ..
+            } => fs::remove_dir(to),
+            Operation::Create {
+                to, is_dir: false, ..
+            } => fs::remove_file(to),
+            Operation::Chmod { to, old_mode, .. } => {
+                fs::set_permissions(to, fs::Permissions::from_mode(*old_mode))
+            }
+            Operation::RemoveDir { to, permissions } => {
+                fs::create_dir(&to)?;
+                match permissions {
+                    Some(permissions) => fs::set_permissions(to, permissions.clone()),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    fn redo(
+        &mut self,
+        home_trash: &Path,
+        progress: &Progress,
+        copy_config: &CopyConfig,
+    ) -> std::io::Result<()> {
+        match self {
+            Operation::Move { from, to } => move_item(from, to, progress),
+            Operation::Trash { from, to } => {
+                *to = trash::move_to_trash(home_trash, from, progress)?;
+                Ok(())
+            }
+            Operation::Restore { from, to } => {
+                move_item(from, to, progress)?;
+                if let (Some(trash_dir), Some(name)) =
+                    (trash::trash_root(home_trash, from), from.file_name())
+                {
+                    let _ = trash::unregister(&trash_dir, name);
+                }
+                Ok(())
+            }
+            Operation::Copy { from, to } => copy_item(from, to, progress, copy_config),
+            Operation::Link { from, to, kind } => make_link(from, to, *kind),
+            Operation::Create {
+                to, is_dir: true, ..
+            } => fs::create_dir(to),
+            Operation::Create {
+                to,
+                is_dir: false,
+                contents,
+                permissions,
+            } => {
+                fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&to)?
+                    .write_all(contents)?;
+                match permissions {
+                    Some(permissions) => fs::set_permissions(to, permissions.clone()),
+                    None => Ok(()),
+                }
+            }
+            Operation::Chmod { to, new_mode, .. } => {
+                fs::set_permissions(to, fs::Permissions::from_mode(*new_mode))
+            }
+            Operation::RemoveDir { to, .. } => fs::remove_dir(to),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    operations: Vec<(Operation, Option<Stamp>)>,
+}
+
+//Operations are recorded one by one, then committed as one entry per command,
+//so that e.g. deleting selected items is undone at once.
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    pending: Vec<(Operation, Option<Stamp>)>,
+    undo: Vec<Entry>,
+    redo: Vec<Entry>,
+    //Paths changed since taken last time, so that sizes of the directories above them are computed again.
+    touched: Vec<PathBuf>,
+}
+
+impl Journal {
+    pub fn record(&mut self, operation: Operation) {
+        self.touched.extend(operation.paths());
+        let stamp = Stamp::of(operation.to());
+        self.pending.push((operation, stamp));
+    }
+
+    pub fn take_touched(&mut self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.touched)
+    }
+
+    pub fn commit(&mut self, name: &str) {
+        if self.pending.is_empty() {
+            return;
+        }
+        self.undo.push(Entry {
+            name: name.to_string(),
+            operations: std::mem::take(&mut self.pending),
+        });
+        if self.undo.len() > MAX_ENTRIES {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    //Operations are undone in reverse order.
+    //If one fails, those undone so far can be redone, and the rest can be undone again.
+    pub fn undo(&mut self, home_trash: &Path, progress: &Progress) -> std::io::Result<String> {
+        let mut entry = match self.undo.pop() {
+            Some(entry) => entry,
+            None => return Ok("Nothing to undo".to_string()),
+        };
+        let mut sandbox = Sandbox::default();
+        for (operation, stamp) in entry.operations.iter().rev() {
+            if let Err(e) = operation.check_undo(stamp, &mut sandbox) {
+                self.undo.push(entry);
+                return Err(e);
+            }
+        }
+        for (operation, _) in entry.operations.iter() {
+            let (bytes, files) = operation.count(home_trash, true);
+            progress.add_total(bytes, files);
+        }
+
+        let mut done = Vec::new();
+        while let Some((mut operation, stamp)) = entry.operations.pop() {
+            let result = operation.undo(home_trash, progress);
+            self.touched.extend(operation.paths());
+            if let Err(e) = result {
+                entry.operations.push((operation, stamp));
+                if !done.is_empty() {
+                    done.reverse();
+                    self.redo.push(Entry {
+                        name: entry.name.clone(),
+                        operations: done,
+                    });
+                }
+                self.undo.push(entry);
+                return Err(e);
+            }
+            let stamp = operation.stamp_undone();
+            done.push((operation, stamp));
+        }
+        done.reverse();
+        let message = format!("Undone: {}", entry.name);
+        self.redo.push(Entry {
+            name: entry.name,
+            operations: done,
+        });
+        Ok(message)
+    }
+
+    pub fn redo(
+        &mut self,
+        home_trash: &Path,
+        progress: &Progress,
+        copy_config: &CopyConfig,
+    ) -> std::io::Result<String> {
+        let mut entry = match self.redo.pop() {
+            Some(entry) => entry,
+            None => return Ok("Nothing to redo".to_string()),
+        };
+        let mut sandbox = Sandbox::default();
+        for (operation, stamp) in entry.operations.iter() {
+            if let Err(e) = operation.check_redo(stamp, &mut sandbox) {
+                self.redo.push(entry);
+                return Err(e);
+            }
+        }
+        for (operation, _) in entry.operations.iter() {
+            let (bytes, files) = operation.count(home_trash, false);
+            progress.add_total(bytes, files);
+        }
+
+        let mut done = Vec::new();
+        entry.operations.reverse();
+        while let Some((mut operation, stamp)) = entry.operations.pop() {
+            let result = operation.redo(home_trash, progress, copy_config);
+            self.touched.extend(operation.paths());
+            if let Err(e) = result {
+                entry.operations.push((operation, stamp));
+                entry.operations.reverse();
+                if !done.is_empty() {
+                    self.undo.push(Entry {
+                        name: entry.name.clone(),
+                        operations: done,
+                    });
+                }
+                self.redo.push(entry);
+                return Err(e);
+            }
+            let stamp = Stamp::of(operation.to());
+            done.push((operation, stamp));
+        }
+        let message = format!("Redone: {}", entry.name);
+        self.undo.push(Entry {
+            name: entry.name,
+            operations: done,
+        });
+        Ok(message)
+    }
+}
+
+fn changed(action: &str, path: &Path) -> Error {
+    Error::other(format!(
+        "cannot {}: {} has changed.",
+        action,
+        escape_name(path.as_os_str())
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testdir::TestDir;
+    use super::*;
+
+    //Operations recorded as `m a/b` or `n a/b/c` records them.
+    fn create(journal: &mut Journal, to: &Path, is_dir: bool) {
+        if is_dir {
+            fs::create_dir(to).unwrap();
+        } else {
+            fs::write(to, "").unwrap();
+        }
+        journal.record(Operation::Create {
+            to: to.to_path_buf(),
+            is_dir,
+            contents: Vec::new(),
+            permissions: None,
+        });
+    }
+
+    #[test]
+    fn test_undo_nested_create() {
+        let temp = TestDir::new();
+        let progress = Progress::new();
+        let a = temp.path().join("a");
+        let mut journal = Journal::default();
+        create(&mut journal, &a, true);
+        create(&mut journal, &a.join("b"), true);
+        create(&mut journal, &a.join("b/c"), false);
+        journal.commit("new file");
+
+        journal.undo(temp.path(), &progress).unwrap();
+        assert!(!a.exists());
+        journal
+            .redo(temp.path(), &progress, &CopyConfig::default())
+            .unwrap();
+        assert!(a.join("b/c").is_file());
+        journal.undo(temp.path(), &progress).unwrap();
+        assert!(!a.exists());
+    }
+
+    #[test]
+    fn test_undo_create_refused_after_change() {
+        let temp = TestDir::new();
+        let progress = Progress::new();
+        let a = temp.path().join("a");
+        let mut journal = Journal::default();
+        create(&mut journal, &a, true);
+        journal.commit("mkdir");
+        fs::remove_dir(&a).unwrap();
+        fs::write(&a, "").unwrap();
+
+        assert!(journal.undo(temp.path(), &progress).is_err());
+        assert!(a.is_file());
+    }
+
+    //Journal with the home trash in a temp dir.
+    struct Setup {
+        temp: TestDir,
+        trash: PathBuf,
+        progress: Progress,
+        journal: Journal,
+    }
+
+    impl Setup {
+        fn new() -> Self {
+            let temp = TestDir::new();
+            let trash = temp.path().join("Trash");
+            trash::make_trash(&trash).unwrap();
+            Setup {
+                temp,
+                trash,
+                progress: Progress::new(),
+                journal: Journal::default(),
+            }
+        }
+
+        fn file(&self, name: &str, contents: &str) -> PathBuf {
+            let path = self.temp.path().join(name);
+            fs::write(&path, contents).unwrap();
+            path
+        }
+
+        fn undo(&mut self) -> std::io::Result<String> {
+            self.journal.undo(&self.trash, &self.progress)
+        }
+
+        fn redo(&mut self) -> std::io::Result<String> {
+            self.journal
+                .redo(&self.trash, &self.progress, &CopyConfig::default())
+        }
+
+        fn trashed(&self) -> Vec<PathBuf> {
+            fs::read_dir(trash::files_dir(&self.trash))
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .collect()
+        }
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_undo_move() {
+        let mut setup = Setup::new();
+        let a = setup.file("a", "a");
+        let b = setup.temp.path().join("b");
+        fs::rename(&a, &b).unwrap();
+        setup.journal.record(Operation::Move {
+            from: a.clone(),
+            to: b.clone(),
+        });
+        setup.journal.commit("rename");
+
+        assert_eq!(setup.undo().unwrap(), "Undone: rename");
+        assert!(a.exists() && !b.exists());
+        assert_eq!(setup.redo().unwrap(), "Redone: rename");
+        assert!(!a.exists() && b.exists());
+
+        //refused when the item is edited after the move
+        fs::write(&b, "edited").unwrap();
+        assert!(setup.undo().is_err());
+        assert!(!a.exists());
+        assert_eq!(read(&b), "edited");
+    }
+
+    #[test]
+    fn test_undo_move_refused_when_from_exists() {
+        let mut setup = Setup::new();
+        let a = setup.file("a", "a");
+        let b = setup.temp.path().join("b");
+        fs::rename(&a, &b).unwrap();
+        setup.journal.record(Operation::Move {
+            from: a.clone(),
+            to: b.clone(),
+        });
+        setup.journal.commit("rename");
+
+        fs::write(&a, "new").unwrap();
+        assert!(setup.undo().is_err());
+        assert_eq!(read(&a), "new");
+        assert_eq!(read(&b), "a");
+    }
+
+    #[test]
+    fn test_undo_trash() {
+        let mut setup = Setup::new();
+        let a = setup.file("a", "a");
+        let to = trash::move_to_trash(&setup.trash, &a, &setup.progress).unwrap();
+        setup.journal.record(Operation::Trash {
+            from: a.clone(),
+            to: to.clone(),
+        });
+        setup.journal.commit("delete");
+
+        setup.undo().unwrap();
+        assert_eq!(read(&a), "a");
+        assert!(setup.trashed().is_empty());
+        assert!(trash::read_info(&setup.trash, to.file_name().unwrap()).is_none());
+
+        setup.redo().unwrap();
+        assert!(!a.exists());
+        assert_eq!(setup.trashed().len(), 1);
+
+        //refused when something has been made at the original path
+        fs::write(&a, "new").unwrap();
+        assert!(setup.undo().is_err());
+        assert_eq!(read(&a), "new");
+        assert_eq!(setup.trashed().len(), 1);
+    }
+
+    #[test]
+    fn test_undo_restore() {
+        let mut setup = Setup::new();
+        let a = setup.file("a", "a");
+        let trashed = trash::move_to_trash(&setup.trash, &a, &setup.progress).unwrap();
+        fs::rename(&trashed, &a).unwrap();
+        trash::unregister(&setup.trash, trashed.file_name().unwrap()).unwrap();
+        setup.journal.record(Operation::Restore {
+            from: trashed,
+            to: a.clone(),
+        });
+        setup.journal.commit("restore");
+
+        setup.undo().unwrap();
+        assert!(!a.exists());
+        let trashed = setup.trashed();
+        assert_eq!(trashed.len(), 1);
+        let info = trash::read_info(&setup.trash, trashed[0].file_name().unwrap()).unwrap();
+        assert_eq!(info.original_path, a);
+
+        setup.redo().unwrap();
+        assert_eq!(read(&a), "a");
+        assert!(setup.trashed().is_empty());
+
+        //refused when the restored item is edited
+        fs::write(&a, "edited").unwrap();
+        assert!(setup.undo().is_err());
+        assert_eq!(read(&a), "edited");
+    }
+
+    #[test]
+    fn test_undo_copy() {
+        let mut setup = Setup::new();
+        let a = setup.file("a", "a");
+        let b = setup.temp.path().join("b");
+        copy_item(&a, &b, &setup.progress, &CopyConfig::default()).unwrap();
+        setup.journal.record(Operation::Copy {
+            from: a.clone(),
+            to: b.clone(),
+        });
+        setup.journal.commit("put");
+
+        //the copy goes to the trash
+        setup.undo().unwrap();
+        assert!(!b.exists());
+        assert_eq!(setup.trashed().len(), 1);
+        setup.redo().unwrap();
+        assert_eq!(read(&b), "a");
+
+        //refused when the copy has been replaced by a directory
+        fs::remove_file(&b).unwrap();
+        fs::create_dir(&b).unwrap();
+        assert!(setup.undo().is_err());
+        assert!(b.is_dir());
+        assert_eq!(setup.trashed().len(), 1);
+    }
+
+    #[test]
+    fn test_redo_copy_refused_when_source_is_gone() {
+        let mut setup = Setup::new();
+        let a = setup.file("a", "a");
+        let b = setup.temp.path().join("b");
+        copy_item(&a, &b, &setup.progress, &CopyConfig::default()).unwrap();
+        setup.journal.record(Operation::Copy {
+            from: a.clone(),
+            to: b.clone(),
+        });
+        setup.journal.commit("put");
+        setup.undo().unwrap();
+
+        fs::remove_file(&a).unwrap();
+        assert!(setup.redo().is_err());
+        assert!(!b.exists());
+    }
+
+    #[test]
+    fn test_undo_link() {
+        let mut setup = Setup::new();
+        let a = setup.file("a", "a");
+        let b = setup.temp.path().join("b");
+        make_link(&a, &b, LinkKind::Symlink).unwrap();
+        setup.journal.record(Operation::Link {
+            from: a.clone(),
+            to: b.clone(),
+            kind: LinkKind::Symlink,
+        });
+        setup.journal.commit("link");
+
+        setup.undo().unwrap();
+        assert!(fs::symlink_metadata(&b).is_err());
+        assert_eq!(read(&a), "a");
+        setup.redo().unwrap();
+        assert_eq!(fs::read_link(&b).unwrap(), a);
+
+        //refused when the link has been replaced by a file
+        fs::remove_file(&b).unwrap();
+        fs::write(&b, "file").unwrap();
+        assert!(setup.undo().is_err());
+        assert_eq!(read(&b), "file");
+    }
+
+    #[test]
+    fn test_undo_chmod() {
+        let mut setup = Setup::new();
+        let a = setup.file("a", "a");
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        fs::set_permissions(&a, fs::Permissions::from_mode(0o644)).unwrap();
+        //changed twice in one entry, so the second is checked against the first
+        for (old_mode, new_mode) in [(0o644, 0o600), (0o600, 0o640)] {
+            fs::set_permissions(&a, fs::Permissions::from_mode(new_mode)).unwrap();
+            setup.journal.record(Operation::Chmod {
+                to: a.clone(),
+                old_mode: 0o100000 | old_mode,
+                new_mode: 0o100000 | new_mode,
+            });
+        }
+        setup.journal.commit("chmod");
+
+        setup.undo().unwrap();
+        assert_eq!(mode(&a), 0o644);
+        setup.redo().unwrap();
+        assert_eq!(mode(&a), 0o640);
+
+        //refused when the mode has been changed since then
+        fs::set_permissions(&a, fs::Permissions::from_mode(0o400)).unwrap();
+        assert!(setup.undo().is_err());
+        assert_eq!(mode(&a), 0o400);
+    }
+
+    #[test]
+    fn test_undo_chained_moves() {
+        let mut setup = Setup::new();
+        let a = setup.file("a", "a");
+        let b = setup.temp.path().join("b");
+        let c = setup.temp.path().join("c");
+        //b does not exist when undoing, so its stamp is taken from undoing the move to c
+        for (from, to) in [(&a, &b), (&b, &c)] {
+            fs::rename(from, to).unwrap();
+            setup.journal.record(Operation::Move {
+                from: from.clone(),
+                to: to.clone(),
+            });
+        }
+        setup.journal.commit("rename");
+
+        setup.undo().unwrap();
+        assert_eq!(read(&a), "a");
+        assert!(!b.exists() && !c.exists());
+        setup.redo().unwrap();
+        assert_eq!(read(&c), "a");
+        assert!(!a.exists() && !b.exists());
+    }
+
+    #[test]
+    fn test_undo_refused_as_a_whole() {
+        let mut setup = Setup::new();
+        let a = setup.file("a", "a");
+        let b = setup.file("b", "b");
+        let dir = setup.temp.path().join("dir");
+        create(&mut setup.journal, &dir, true);
+        for from in [&a, &b] {
+            let to = dir.join(from.file_name().unwrap());
+            fs::rename(from, &to).unwrap();
+            setup.journal.record(Operation::Move {
+                from: from.clone(),
+                to,
+            });
+        }
+        setup.journal.commit("put");
+
+        //a is edited, so nothing is undone, not even moving b back, which is checked first
+        fs::write(dir.join("a"), "edited").unwrap();
+        assert!(setup.undo().is_err());
+        assert!(dir.join("b").exists());
+        assert!(!b.exists());
+    }
+
+    #[test]
+    fn test_undo_merge() {
+        let mut setup = Setup::new();
+        let from = setup.temp.path().join("from");
+        let to = setup.temp.path().join("to");
+        fs::create_dir(&from).unwrap();
+        fs::create_dir(&to).unwrap();
+        fs::write(from.join("x"), "x").unwrap();
+        fs::set_permissions(&from, fs::Permissions::from_mode(0o750)).unwrap();
+        let permissions = fs::metadata(&from).unwrap().permissions();
+
+        //cut `from` merged into `to`: its contents are moved, then it is removed
+        fs::rename(from.join("x"), to.join("x")).unwrap();
+        setup.journal.record(Operation::Move {
+            from: from.join("x"),
+            to: to.join("x"),
+        });
+        fs::remove_dir(&from).unwrap();
+        setup.journal.record(Operation::RemoveDir {
+            to: from.clone(),
+            permissions: Some(permissions),
+        });
+        setup.journal.commit("put");
+
+        setup.undo().unwrap();
+        assert_eq!(read(&from.join("x")), "x");
+        assert!(!to.join("x").exists());
+        let mode = fs::metadata(&from).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o750);
+
+        setup.redo().unwrap();
+        assert!(!from.exists());
+        assert_eq!(read(&to.join("x")), "x");
+
+        //refused when the directory has been made again with an item in it
+        fs::create_dir(&from).unwrap();
+        fs::write(from.join("x"), "new").unwrap();
+        assert!(setup.undo().is_err());
+        assert_eq!(read(&from.join("x")), "new");
+        assert_eq!(read(&to.join("x")), "x");
+    }
+
+    #[test]
+    fn test_stamp_detects_modified_time() {
+        let mut setup = Setup::new();
+        let a = setup.file("a", "a");
+        let b = setup.temp.path().join("b");
+        fs::rename(&a, &b).unwrap();
+        setup.journal.record(Operation::Move {
+            from: a.clone(),
+            to: b.clone(),
+        });
+        setup.journal.commit("rename");
+
+        //same length, but written later
+        let modified = fs::metadata(&b).unwrap().modified().unwrap();
+        fs::write(&b, "b").unwrap();
+        filetime::set_file_mtime(
+            &b,
+            filetime::FileTime::from_system_time(modified + std::time::Duration::from_secs(1)),
+        )
+        .unwrap();
+        assert!(setup.undo().is_err());
+        assert_eq!(read(&b), "b");
+    }
+}
@@ -1,9 +1,17 @@
 mod config;
+mod dirsize;
 mod functions;
 mod help;
+mod input;
+mod journal;
 mod nums;
+mod progress;
 mod run;
 mod state;
+#[cfg(test)]
+mod testdir;
+mod trash;
+mod usage;
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
@@ -0,0 +1,97 @@
+use super::config::FormatConfig;
+use super::functions::format_size;
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+const BAR_WIDTH: usize = 20;
+
+//Shared between the main thread, which draws the status line, and the worker thread.
+#[derive(Debug, Default)]
+pub struct Progress {
+    bytes_done: AtomicU64,
+    bytes_total: AtomicU64,
+    files_done: AtomicU64,
+    files_total: AtomicU64,
+    cancelled: AtomicBool,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn reset(&self) {
+        self.bytes_done.store(0, Ordering::Relaxed);
+        self.bytes_total.store(0, Ordering::Relaxed);
+        self.files_done.store(0, Ordering::Relaxed);
+        self.files_total.store(0, Ordering::Relaxed);
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+
+    pub fn add_total(&self, bytes: u64, files: u64) {
+        self.bytes_total.fetch_add(bytes, Ordering::Relaxed);
+        self.files_total.fetch_add(files, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes(&self, bytes: u64) {
+        self.bytes_done.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_file(&self) {
+        self.files_done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    //Called between chunks so that Esc stops the operation.
+    pub fn check(&self) -> std::io::Result<()> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            Err(Error::new(ErrorKind::Interrupted, "cancelled."))
+        } else {
+            Ok(())
+        }
+    }
+
+    //e.g. `[#######             ] 3/10 files 120MB/350MB 40MB/s ETA 6s`
+    pub fn format(&self, elapsed: Duration, format: &FormatConfig) -> String {
+        let bytes_done = self.bytes_done.load(Ordering::Relaxed);
+        let bytes_total = self.bytes_total.load(Ordering::Relaxed);
+        let files_done = self.files_done.load(Ordering::Relaxed);
+        let files_total = self.files_total.load(Ordering::Relaxed);
+
+        let filled = if bytes_total == 0 {
+            0
+        } else {
+            (bytes_done.min(bytes_total) as u128 * BAR_WIDTH as u128 / bytes_total as u128) as usize
+        };
+        let bar: String = (0..BAR_WIDTH)
+            .map(|i| if i < filled { '#' } else { ' ' })
+            .collect();
+
+        let secs = elapsed.as_secs_f64();
+        let throughput = if secs > 0.0 {
+            (bytes_done as f64 / secs) as u64
+        } else {
+            0
+        };
+        let eta = if throughput > 0 && bytes_total > bytes_done {
+            format!(" ETA {}s", (bytes_total - bytes_done) / throughput)
+        } else {
+            "".to_string()
+        };
+
+        format!(
+            "[{}] {}/{} files {}/{} {}/s{}",
+            bar,
+            files_done,
+            files_total,
+            format_size(bytes_done, format),
+            format_size(bytes_total, format),
+            format_size(throughput, format),
+            eta
+        )
+    }
+}
@@ -1,19 +1,25 @@
-use super::config::SortKey;
+use super::config::{ConflictPolicy, SortKey};
 use super::functions::*;
 use super::help::HELP;
+use super::input::Input;
 use super::nums::*;
 use super::state::*;
-use std::ffi::OsStr;
+use super::trash::TRASH;
+use super::trash::{self, TrashSortKey};
+use super::usage::Usage;
+use std::ffi::{OsStr, OsString};
 // use clipboard::{ClipboardContext, ClipboardProvider};
 use log::debug;
 use log::error;
-use std::io::{stdin, stdout, Write};
-use std::path::{Path, PathBuf};
+use log::info;
+use std::io::{stdout, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use termion::cursor::DetectCursorPos;
 use termion::event::Key;
-use termion::input::TermRead;
 use termion::raw::IntoRawMode;
 use termion::{clear, cursor, screen};
+use unicode_width::UnicodeWidthStr;
 
 pub fn run(arg: PathBuf) {
     env_logger::init();
@@ -22,7 +28,9 @@ pub fn run(arg: PathBuf) {
     let mut config_dir = dirs::config_dir().unwrap_or_else(|| panic!("cannot read config dir."));
     config_dir.push(FX_CONFIG_DIR);
     let config_file = config_dir.join(PathBuf::from(CONFIG_FILE));
-    let trash_dir = config_dir.join(PathBuf::from(TRASH));
+    let trash_dir = dirs::data_dir()
+        .unwrap_or_else(|| panic!("cannot read data dir."))
+        .join(PathBuf::from(TRASH));
     make_config(&config_file, &trash_dir)
         .unwrap_or_else(|_| panic!("cannot make config file or trash dir."));
 
@@ -39,21 +47,28 @@ pub fn run(arg: PathBuf) {
 
     let mut state = State::new();
 
-    let time_start = if column >= 49 { 31 } else { column - 17 };
-    let name_max: usize = if column >= 49 {
-        29
-    } else {
-        (time_start - 2).into()
-    };
-    state.layout = Layout {
-        terminal_row: row,
-        terminal_column: column,
-        name_max_len: name_max,
-        time_start_pos: time_start,
-    };
+    state.layout = Layout::new(column, row, &state.columns, &state.format);
     state.current_dir = arg.canonicalize().unwrap();
     state.update_list();
     state.trash_dir = trash_dir;
+    state.templates_dir = config_dir.join(TEMPLATES);
+    //moving the old trash and purging a large trash take time, so they do not hold up the start
+    let (trash_dir, trash_config) = (state.trash_dir.clone(), state.trash_config.clone());
+    let old_trash = config_dir.join(trash::OLD_TRASH);
+    std::thread::spawn(move || {
+        if let Some(home) = dirs::home_dir() {
+            match trash::migrate_old_trash(&old_trash, &trash_dir, &home) {
+                Ok(i) if i > 0 => info!("{} items moved from the old trash.", i),
+                Ok(_) => {}
+                Err(e) => error!("cannot move items from the old trash: {}", e),
+            }
+        }
+        match trash::purge(&trash_dir, &trash_config) {
+            Ok(i) if i > 0 => info!("{} items purged from the trash.", i),
+            Ok(_) => {}
+            Err(e) => error!("cannot purge the trash: {}", e),
+        }
+    });
 
     let mut filtered = false;
 
@@ -80,10 +95,13 @@ pub fn run(arg: PathBuf) {
 
     let mut p_memo_v: Vec<CursorMemo> = Vec::new();
     let mut c_memo_v: Vec<ChildMemo> = Vec::new();
-    let mut stdin = stdin().keys();
+    let mut stdin = Input::new();
     debug!("finished initial setup.");
 
+    let mut sizes_shown = state.dir_sizes.generation();
+
     'main: loop {
+        show_dir_sizes(&mut state, &mut stdin, &mut nums, &mut sizes_shown);
         let len = state.list.len();
         let (_, y) = screen.cursor_pos().unwrap();
         let input = stdin.next();
@@ -228,23 +246,16 @@ pub fn run(arg: PathBuf) {
                                         state.update_list();
 
                                         match c_memo_v.pop() {
-                                            Some(memo) => {
-                                                if state.current_dir == memo.dir_path {
-                                                    nums = memo.cursor_memo.num;
-                                                    clear_and_show(&state.current_dir);
-                                                    state.list_up(nums.skip);
-                                                    state.move_cursor(
-                                                        &nums,
-                                                        memo.cursor_memo.cursor_pos,
-                                                    );
-                                                } else {
-                                                    clear_and_show(&state.current_dir);
-                                                    state.list_up(0);
-                                                    nums.reset();
-                                                    state.move_cursor(&nums, STARTING_POINT);
-                                                }
+                                            Some(memo) if state.current_dir == memo.dir_path => {
+                                                nums = memo.cursor_memo.num;
+                                                clear_and_show(&state.current_dir);
+                                                state.list_up(nums.skip);
+                                                state.move_cursor(
+                                                    &nums,
+                                                    memo.cursor_memo.cursor_pos,
+                                                );
                                             }
-                                            None => {
+                                            _ => {
                                                 clear_and_show(&state.current_dir);
                                                 state.list_up(0);
                                                 nums.reset();
@@ -297,31 +308,7 @@ pub fn run(arg: PathBuf) {
                                     state.move_cursor(&nums, memo.cursor_pos);
                                 }
                                 None => match pre.file_name() {
-                                    Some(name) => {
-                                        let mut new_pos = 0;
-                                        for (i, item) in state.list.iter().enumerate() {
-                                            let name_as_os_str: &OsStr = item.file_name.as_ref();
-                                            if name_as_os_str == name {
-                                                new_pos = i;
-                                            }
-                                        }
-                                        nums.index = new_pos;
-
-                                        if nums.index
-                                            >= (state.layout.terminal_row - (STARTING_POINT + 3))
-                                                .into()
-                                        {
-                                            nums.skip = (nums.index - 3) as u16;
-                                            clear_and_show(&state.current_dir);
-                                            state.list_up(nums.skip);
-                                            state.move_cursor(&nums, STARTING_POINT + 3);
-                                        } else {
-                                            nums.skip = 0;
-                                            clear_and_show(&state.current_dir);
-                                            state.list_up(0);
-                                            state.move_cursor(&nums, (nums.index + 3) as u16);
-                                        }
-                                    }
+                                    Some(name) => move_to_item(&state, &mut nums, name),
                                     None => {
                                         nums.reset();
                                         clear_and_show(&state.current_dir);
@@ -341,7 +328,7 @@ pub fn run(arg: PathBuf) {
                     if len == 0 {
                         continue;
                     }
-                    let mut item = state.list.get_mut(nums.index).unwrap();
+                    let item = state.list.get_mut(nums.index).unwrap();
                     item.selected = true;
 
                     clear_and_show(&state.current_dir);
@@ -368,11 +355,10 @@ pub fn run(arg: PathBuf) {
                                         nums.go_down();
 
                                         if nums.index > start_pos {
-                                            let mut item = state.list.get_mut(nums.index).unwrap();
+                                            let item = state.list.get_mut(nums.index).unwrap();
                                             item.selected = true;
                                         } else if nums.index < start_pos {
-                                            let mut item =
-                                                state.list.get_mut(nums.index - 1).unwrap();
+                                            let item = state.list.get_mut(nums.index - 1).unwrap();
                                             item.selected = false;
                                         }
 
@@ -384,11 +370,10 @@ pub fn run(arg: PathBuf) {
                                         nums.go_down();
 
                                         if nums.index > start_pos {
-                                            let mut item = state.list.get_mut(nums.index).unwrap();
+                                            let item = state.list.get_mut(nums.index).unwrap();
                                             item.selected = true;
                                         } else if nums.index <= start_pos {
-                                            let mut item =
-                                                state.list.get_mut(nums.index - 1).unwrap();
+                                            let item = state.list.get_mut(nums.index - 1).unwrap();
                                             item.selected = false;
                                         }
 
@@ -407,11 +392,10 @@ pub fn run(arg: PathBuf) {
                                         nums.go_up();
 
                                         if nums.index >= start_pos {
-                                            let mut item =
-                                                state.list.get_mut(nums.index + 1).unwrap();
+                                            let item = state.list.get_mut(nums.index + 1).unwrap();
                                             item.selected = false;
                                         } else if nums.index < start_pos {
-                                            let mut item = state.list.get_mut(nums.index).unwrap();
+                                            let item = state.list.get_mut(nums.index).unwrap();
                                             item.selected = true;
                                         }
 
@@ -423,11 +407,10 @@ pub fn run(arg: PathBuf) {
                                         nums.go_up();
 
                                         if nums.index >= start_pos {
-                                            let mut item =
-                                                state.list.get_mut(nums.index + 1).unwrap();
+                                            let item = state.list.get_mut(nums.index + 1).unwrap();
                                             item.selected = false;
                                         } else if nums.index < start_pos {
-                                            let mut item = state.list.get_mut(nums.index).unwrap();
+                                            let item = state.list.get_mut(nums.index).unwrap();
                                             item.selected = true;
                                         }
 
@@ -505,37 +488,26 @@ pub fn run(arg: PathBuf) {
                                     print_info("Processing...", y);
                                     screen.flush().unwrap();
 
-                                    state.registered.clear();
-                                    let iter = state.list.clone().into_iter();
-                                    let mut i = 0;
-                                    for item in iter {
-                                        if item.selected {
-                                            match item.file_type {
-                                                FileType::Directory => {
-                                                    if let Err(e) = state.remove_and_yank_dir(item)
-                                                    {
-                                                        print_warning(e, y);
-                                                        break;
-                                                    }
-                                                }
-                                                FileType::File | FileType::Symlink => {
-                                                    if let Err(e) = state.remove_and_yank_file(item)
-                                                    {
-                                                        print_warning(e, y);
-                                                        break;
-                                                    }
-                                                }
-                                            }
-                                            i += 1;
-                                        }
-                                    }
+                                    let selected = state.selected_items();
+                                    let result = run_in_background(
+                                        &mut state,
+                                        &mut screen,
+                                        &mut stdin,
+                                        y,
+                                        move |ops| ops.remove_and_yank_selected(selected),
+                                    );
                                     clear_and_show(&state.current_dir);
                                     state.update_list();
                                     state.list_up(nums.skip);
 
-                                    let mut delete_message: String = i.to_string();
-                                    delete_message.push_str(" items deleted");
-                                    print_info(delete_message, y);
+                                    match result {
+                                        Ok(i) => {
+                                            let mut delete_message: String = i.to_string();
+                                            delete_message.push_str(" items deleted");
+                                            print_info(delete_message, y);
+                                        }
+                                        Err(e) => print_warning(e, y),
+                                    }
                                     print!(" ");
 
                                     let new_len = state.list.len();
@@ -552,6 +524,33 @@ pub fn run(arg: PathBuf) {
                                     break;
                                 }
 
+                                Key::Char('l') | Key::Char('\n') | Key::Right => {
+                                    print!("{}", screen::ToAlternateScreen);
+                                    let result = state.open_selected();
+                                    print!("{}", screen::ToAlternateScreen);
+
+                                    state.reset_selection();
+                                    clear_and_show(&state.current_dir);
+                                    state.list_up(nums.skip);
+                                    print!("{}", cursor::Hide);
+                                    match result {
+                                        Ok(0) => print_info(
+                                            "No files opened (directories are skipped)",
+                                            y,
+                                        ),
+                                        Ok(i) => {
+                                            let mut open_message: String = i.to_string();
+                                            open_message.push_str(" items opened");
+                                            print_info(open_message, y);
+                                        }
+                                        Err(e) => {
+                                            print_warning(format!("{}. Check your config!", e), y);
+                                        }
+                                    }
+                                    state.move_cursor(&nums, y);
+                                    break;
+                                }
+
                                 Key::Char('y') => {
                                     state.yank_item(nums.index, true);
                                     state.reset_selection();
@@ -567,6 +566,97 @@ pub fn run(arg: PathBuf) {
                                     break;
                                 }
 
+                                Key::Char('x') => {
+                                    state.cut_item(nums.index, true);
+                                    state.reset_selection();
+                                    clear_and_show(&state.current_dir);
+                                    state.list_up(nums.skip);
+
+                                    let mut cut_message: String =
+                                        state.registered.len().to_string();
+                                    cut_message.push_str(" items cut");
+                                    print_info(cut_message, y);
+
+                                    state.move_cursor(&nums, y);
+                                    break;
+                                }
+
+                                Key::Char('M') => {
+                                    let items: Vec<ItemInfo> = state
+                                        .list
+                                        .iter()
+                                        .filter(|item| item.selected)
+                                        .cloned()
+                                        .collect();
+                                    let result = chmod(&mut state, &mut screen, &mut stdin, &items);
+                                    state.reset_selection();
+                                    clear_and_show(&state.current_dir);
+                                    state.update_list();
+                                    state.list_up(nums.skip);
+                                    match result {
+                                        Ok(message) => print_info(message, y),
+                                        Err(e) => print_warning(e, y),
+                                    }
+                                    state.move_cursor(&nums, y);
+                                    break;
+                                }
+
+                                Key::Char(':') => {
+                                    print!("{}", cursor::Show);
+                                    let pattern =
+                                        read_line(&mut screen, &mut stdin, ":rename-pattern ", "");
+                                    print!("{}", cursor::Hide);
+                                    let result = match pattern {
+                                        Some(pattern) => {
+                                            let names: Vec<OsString> = state
+                                                .list
+                                                .iter()
+                                                .filter(|item| item.selected)
+                                                .map(|item| item.file_name.clone())
+                                                .collect();
+                                            rename_by_pattern(
+                                                &mut state,
+                                                &mut screen,
+                                                &mut stdin,
+                                                names,
+                                                &pattern,
+                                            )
+                                        }
+                                        None => Ok("".to_string()),
+                                    };
+                                    state.reset_selection();
+                                    clear_and_show(&state.current_dir);
+                                    state.update_list();
+                                    state.list_up(nums.skip);
+                                    match result {
+                                        Ok(message) => print_info(message, y),
+                                        Err(e) => print_warning(e, y),
+                                    }
+                                    state.move_cursor(&nums, y);
+                                    break;
+                                }
+
+                                Key::Char('c') => {
+                                    let names: Vec<OsString> = state
+                                        .list
+                                        .iter()
+                                        .filter(|item| item.selected)
+                                        .map(|item| item.file_name.clone())
+                                        .collect();
+                                    let result = bulk_rename(&mut state, names);
+                                    state.reset_selection();
+                                    clear_and_show(&state.current_dir);
+                                    state.update_list();
+                                    state.list_up(nums.skip);
+                                    print!("{}", cursor::Hide);
+                                    match result {
+                                        Ok(message) => print_info(message, y),
+                                        Err(e) => print_warning(e, y),
+                                    }
+                                    state.move_cursor(&nums, y);
+                                    break;
+                                }
+
                                 Key::Esc => {
                                     state.reset_selection();
                                     clear_and_show(&state.current_dir);
@@ -590,6 +680,9 @@ pub fn run(arg: PathBuf) {
                             state.sort_by = SortKey::Time;
                         }
                         SortKey::Time => {
+                            state.sort_by = SortKey::Size;
+                        }
+                        SortKey::Size => {
                             state.sort_by = SortKey::Name;
                         }
                     }
@@ -600,6 +693,16 @@ pub fn run(arg: PathBuf) {
                     state.move_cursor(&nums, STARTING_POINT);
                 }
 
+                //Compute recursive sizes of the directories again.
+                Key::Char('s') => {
+                    if state.shows_dir_sizes() {
+                        state.request_dir_sizes(true);
+                        print_info("Computing directory sizes...", y);
+                    } else {
+                        print_warning("Add the Size column or sort by size to see sizes.", y);
+                    }
+                }
+
                 Key::Char('d') => {
                     if len == 0 {
                         continue;
@@ -617,26 +720,21 @@ pub fn run(arg: PathBuf) {
                                         print_info("Processing...", y);
                                         screen.flush().unwrap();
 
-                                        state.registered.clear();
                                         let item = state.get_item(nums.index).unwrap().clone();
-                                        match item.file_type {
-                                            FileType::Directory => {
-                                                if let Err(e) = state.remove_and_yank_dir(item) {
-                                                    print!("{}", cursor::Hide);
-                                                    print_warning(e, y);
-                                                    state.move_cursor(&nums, y);
-                                                    break 'delete;
-                                                }
-                                            }
-                                            FileType::File | FileType::Symlink => {
-                                                if let Err(e) = state.remove_and_yank_file(item) {
-                                                    clear_and_show(&state.current_dir);
-                                                    print!("{}", cursor::Hide);
-                                                    print_warning(e, y);
-                                                    state.move_cursor(&nums, y);
-                                                    break 'delete;
-                                                }
-                                            }
+                                        if let Err(e) = run_in_background(
+                                            &mut state,
+                                            &mut screen,
+                                            &mut stdin,
+                                            y,
+                                            move |ops| ops.remove_and_yank_selected(vec![item]),
+                                        ) {
+                                            clear_and_show(&state.current_dir);
+                                            state.update_list();
+                                            state.list_up(nums.skip);
+                                            print!("{}", cursor::Hide);
+                                            print_warning(e, y);
+                                            state.move_cursor(&nums, y);
+                                            break 'delete;
                                         }
 
                                         clear_and_show(&state.current_dir);
@@ -703,28 +801,256 @@ pub fn run(arg: PathBuf) {
                     }
                 }
 
+                Key::Char('x') => {
+                    if len == 0 {
+                        continue;
+                    }
+                    print!("{}{}x", cursor::Goto(2, 2), clear::CurrentLine,);
+                    print!("{}", cursor::Show);
+
+                    screen.flush().unwrap();
+
+                    'cut: loop {
+                        let input = stdin.next();
+                        if let Some(Ok(key)) = input {
+                            match key {
+                                Key::Char('x') => {
+                                    state.cut_item(nums.index, false);
+                                    print!("{}", clear::CurrentLine);
+                                    print!("{}{}", cursor::Goto(2, 2), DOWN_ARROW);
+                                    print!("{}", cursor::Hide);
+                                    state.move_cursor(&nums, y);
+                                    print_info("1 item cut", y);
+                                    break 'cut;
+                                }
+
+                                _ => {
+                                    print!("{}", clear::CurrentLine);
+                                    print!("{}{}", cursor::Goto(2, 2), DOWN_ARROW);
+                                    print!("{}", cursor::Hide);
+                                    state.move_cursor(&nums, y);
+                                    break 'cut;
+                                }
+                            }
+                        }
+                    }
+                }
+
                 Key::Char('p') => {
                     if state.registered.is_empty() {
                         continue;
                     }
+                    let plan = match make_put_plan(&state, &mut screen, &mut stdin, y) {
+                        Some(plan) => plan,
+                        None => {
+                            print!("{}", clear::CurrentLine);
+                            print!("{}{}", cursor::Goto(2, 2), DOWN_ARROW);
+                            state.move_cursor(&nums, y);
+                            continue;
+                        }
+                    };
                     print_info("Processing...", y);
                     screen.flush().unwrap();
 
-                    if let Err(e) = state.put_items() {
-                        print_warning(e, y);
+                    let cut = state.cut;
+                    let result =
+                        run_in_background(&mut state, &mut screen, &mut stdin, y, move |ops| {
+                            ops.put_items(&plan)
+                        });
+                    clear_and_show(&state.current_dir);
+                    state.update_list();
+                    state.list_up(nums.skip);
+
+                    let put_len = match result {
+                        Ok(put_len) => put_len,
+                        Err(e) => {
+                            print_warning(e, y);
+                            continue;
+                        }
+                    };
+
+                    let mut put_message: String = put_len.to_string();
+                    if cut {
+                        put_message.push_str(" items moved");
+                    } else {
+                        put_message.push_str(" items inserted");
+                    }
+                    print_info(put_message, y);
+                    state.move_cursor(&nums, y);
+                }
+
+                Key::Char('P') => {
+                    if state.registered.is_empty() {
                         continue;
                     }
+                    print!("{}{}P", cursor::Goto(2, 2), clear::CurrentLine,);
+                    print!("{}", cursor::Show);
+
+                    screen.flush().unwrap();
+
+                    let kind = match stdin.next() {
+                        Some(Ok(Key::Char('s'))) => Some(LinkKind::Symlink),
+                        Some(Ok(Key::Char('r'))) => Some(LinkKind::RelativeSymlink),
+                        Some(Ok(Key::Char('h'))) => Some(LinkKind::HardLink),
+                        _ => None,
+                    };
+                    print!("{}", clear::CurrentLine);
+                    print!("{}{}", cursor::Goto(2, 2), DOWN_ARROW);
+                    print!("{}", cursor::Hide);
+
+                    if let Some(kind) = kind {
+                        let result = state.link_items(kind);
+                        clear_and_show(&state.current_dir);
+                        state.update_list();
+                        state.list_up(nums.skip);
+                        match result {
+                            Ok(count) => print_info(format!("{} links created", count), y),
+                            Err(e) => print_warning(e, y),
+                        }
+                    }
+                    state.move_cursor(&nums, y);
+                }
 
+                Key::Char('u') | Key::Ctrl('r') => {
+                    print_info("Processing...", y);
+                    screen.flush().unwrap();
+
+                    let result = if key == Key::Char('u') {
+                        run_in_background(&mut state, &mut screen, &mut stdin, y, |ops| ops.undo())
+                    } else {
+                        run_in_background(&mut state, &mut screen, &mut stdin, y, |ops| ops.redo())
+                    };
                     clear_and_show(&state.current_dir);
                     state.update_list();
                     state.list_up(nums.skip);
+                    match result {
+                        Ok(message) => print_info(message, y),
+                        Err(e) => print_warning(e, y),
+                    }
 
-                    let mut put_message: String = state.registered.len().to_string();
-                    put_message.push_str(" items inserted");
-                    print_info(put_message, y);
+                    let new_len = state.list.len();
+                    if new_len == 0 {
+                        nums.reset();
+                        state.move_cursor(&nums, STARTING_POINT);
+                    } else if nums.index > new_len - 1 {
+                        let new_y = y - (nums.index - (new_len - 1)) as u16;
+                        nums.index = new_len - 1;
+                        state.move_cursor(&nums, new_y)
+                    } else {
+                        state.move_cursor(&nums, y);
+                    }
+                }
+
+                Key::Char('m') | Key::Char('n') => {
+                    let is_dir = key == Key::Char('m');
+                    print!("{}", cursor::Show);
+                    let prompt = if is_dir { "mkdir: " } else { "new file: " };
+                    let path = read_line(&mut screen, &mut stdin, prompt, "");
+                    print!("{}", cursor::Hide);
+                    let path = match path {
+                        Some(path) if !path.trim().is_empty() => path,
+                        _ => {
+                            print!("{}", clear::CurrentLine);
+                            print!("{}{}", cursor::Goto(2, 2), DOWN_ARROW);
+                            state.move_cursor(&nums, y);
+                            continue;
+                        }
+                    };
+
+                    match state.make_item(path.trim(), is_dir) {
+                        Ok(top) => {
+                            if !is_dir && state.open_new_file {
+                                print!("{}", screen::ToAlternateScreen);
+                                let _ = open_in_editor(
+                                    &state.editor(),
+                                    &state.current_dir.join(path.trim()),
+                                );
+                                print!("{}", screen::ToAlternateScreen);
+                                print!("{}", cursor::Hide);
+                            }
+                            state.update_list();
+                            if let Some(name) = top.file_name() {
+                                move_to_item(&state, &mut nums, name);
+                            }
+                        }
+                        Err(e) => {
+                            clear_and_show(&state.current_dir);
+                            state.list_up(nums.skip);
+                            print_warning(e, y);
+                            state.move_cursor(&nums, y);
+                        }
+                    }
+                }
+
+                Key::Char('M') => {
+                    let item = match state.get_item(nums.index) {
+                        Ok(item) => item.clone(),
+                        Err(_) => continue,
+                    };
+                    let result = chmod(&mut state, &mut screen, &mut stdin, &[item]);
+                    clear_and_show(&state.current_dir);
+                    state.update_list();
+                    state.list_up(nums.skip);
+                    match result {
+                        Ok(message) => print_info(message, y),
+                        Err(e) => print_warning(e, y),
+                    }
                     state.move_cursor(&nums, y);
                 }
 
+                Key::Char('T') => {
+                    let template = match pick_template(&state, &mut screen, &mut stdin) {
+                        Ok(template) => template,
+                        Err(e) => {
+                            clear_and_show(&state.current_dir);
+                            state.list_up(nums.skip);
+                            print_warning(e, y);
+                            state.move_cursor(&nums, y);
+                            continue;
+                        }
+                    };
+                    clear_and_show(&state.current_dir);
+                    state.list_up(nums.skip);
+                    let name = template.as_ref().and_then(|template| {
+                        let initial = template
+                            .file_name()
+                            .map(|name| replace_control(&name.to_string_lossy()))
+                            .unwrap_or_default();
+                        print!("{}", cursor::Show);
+                        let name = read_line(&mut screen, &mut stdin, "new file: ", &initial);
+                        print!("{}", cursor::Hide);
+                        name
+                    });
+                    let (template, name) = match (template, name) {
+                        (Some(template), Some(name)) => (template, name),
+                        _ => {
+                            print!("{}", clear::CurrentLine);
+                            print!("{}{}", cursor::Goto(2, 2), DOWN_ARROW);
+                            state.move_cursor(&nums, y);
+                            continue;
+                        }
+                    };
+
+                    match state.make_from_template(&template, name.trim()) {
+                        Ok(to) => {
+                            if state.open_new_file {
+                                print!("{}", screen::ToAlternateScreen);
+                                let _ = open_in_editor(&state.editor(), &to);
+                                print!("{}", screen::ToAlternateScreen);
+                                print!("{}", cursor::Hide);
+                            }
+                            state.update_list();
+                            if let Some(name) = to.file_name() {
+                                move_to_item(&state, &mut nums, name);
+                            }
+                        }
+                        Err(e) => {
+                            print_warning(e, y);
+                            state.move_cursor(&nums, y);
+                        }
+                    }
+                }
+
                 Key::Char('c') => {
                     if len == 0 {
                         continue;
@@ -732,7 +1058,9 @@ pub fn run(arg: PathBuf) {
                     print!("{}", cursor::Show);
                     let item = state.get_item(nums.index).unwrap();
 
-                    let mut rename = item.file_name.chars().collect::<Vec<char>>();
+                    //names are edited as shown, so keep the original unless edited
+                    let initial = replace_control(&item.file_name.to_string_lossy());
+                    let mut rename = initial.chars().collect::<Vec<char>>();
                     print!(
                         "{}{}{} {}",
                         cursor::Goto(2, 2),
@@ -752,10 +1080,13 @@ pub fn run(arg: PathBuf) {
                                 Key::Char('\n') => {
                                     let rename = rename.iter().collect::<String>();
                                     let mut to = state.current_dir.clone();
-                                    to.push(rename);
-                                    if let Err(e) =
-                                        std::fs::rename(Path::new(&item.file_path), Path::new(&to))
-                                    {
+                                    if rename == initial {
+                                        to.push(&item.file_name);
+                                    } else {
+                                        to.push(rename);
+                                    }
+                                    let from = item.file_path.clone();
+                                    if let Err(e) = state.rename_item(&from, &to) {
                                         print!("{}", cursor::Hide);
                                         print_warning(e, y);
                                         break;
@@ -908,6 +1239,7 @@ pub fn run(arg: PathBuf) {
                                         .filter(|entry| {
                                             entry
                                                 .file_name
+                                                .to_string_lossy()
                                                 .contains(&keyword.iter().collect::<String>())
                                         })
                                         .collect();
@@ -939,6 +1271,7 @@ pub fn run(arg: PathBuf) {
                                         .filter(|entry| {
                                             entry
                                                 .file_name
+                                                .to_string_lossy()
                                                 .contains(&keyword.iter().collect::<String>())
                                         })
                                         .collect();
@@ -1000,10 +1333,8 @@ pub fn run(arg: PathBuf) {
                                     } else if command == vec!['h'] {
                                         print!("{}", cursor::Hide);
                                         print!("{}{}", clear::All, cursor::Goto(1, 1));
-                                        let mut i = 2;
-                                        for line in HELP.lines() {
+                                        for (i, line) in (2..).zip(HELP.lines()) {
                                             println!("{}{}", line, cursor::Goto(1, i));
-                                            i += 1;
                                         }
                                         println!("\nInput any key to go back.");
                                         let _ = stdin.next();
@@ -1029,37 +1360,211 @@ pub fn run(arg: PathBuf) {
                                         }
                                     }
 
-                                    if c == "empty" && args.is_empty() {
-                                        print_warning(WHEN_EMPTY, y);
-                                        screen.flush().unwrap();
-
-                                        'empty: loop {
-                                            let input = stdin.next();
-                                            if let Some(Ok(key)) = input {
-                                                match key {
-                                                    Key::Char('y') | Key::Char('Y') => {
-                                                        print_info("Processing...", y);
-                                                        screen.flush().unwrap();
-
-                                                        if let Err(e) = std::fs::remove_dir_all(
-                                                            &state.trash_dir,
-                                                        ) {
-                                                            print!("{}", cursor::Hide);
-                                                            print_warning(e, y);
-                                                            continue 'main;
-                                                        }
-                                                        if let Err(e) =
-                                                            std::fs::create_dir(&state.trash_dir)
-                                                        {
-                                                            print!("{}", cursor::Hide);
-                                                            print_warning(e, y);
-                                                            continue 'main;
-                                                        }
-                                                        break 'empty;
-                                                    }
-                                                    _ => {
-                                                        break 'empty;
-                                                    }
+                                    if c == "restore" && args.is_empty() {
+                                        print!("{}", cursor::Hide);
+                                        if trash::is_files_dir(&state.trash_dir, &state.current_dir)
+                                        {
+                                            //restore the item under the cursor
+                                            let item = match state.get_item(nums.index) {
+                                                Ok(item) => item.clone(),
+                                                Err(e) => {
+                                                    print_warning(e, y);
+                                                    break 'command;
+                                                }
+                                            };
+                                            match state.restore_item(&item) {
+                                                Err(e) => {
+                                                    print_warning(e, y);
+                                                    break 'command;
+                                                }
+                                                Ok(to) => {
+                                                    clear_and_show(&state.current_dir);
+                                                    state.update_list();
+                                                    state.list_up(nums.skip);
+                                                    let cursor_pos = if state.list.is_empty() {
+                                                        STARTING_POINT
+                                                    } else if nums.index == len - 1 {
+                                                        nums.go_up();
+                                                        y - 1
+                                                    } else {
+                                                        y
+                                                    };
+                                                    print_info(
+                                                        format!(
+                                                            "Restored to {}",
+                                                            escape_name(to.as_os_str())
+                                                        ),
+                                                        cursor_pos,
+                                                    );
+                                                    state.move_cursor(&nums, cursor_pos);
+                                                }
+                                            }
+                                        } else {
+                                            //restore the items deleted last time
+                                            match state.restore_registered() {
+                                                Err(e) => {
+                                                    print_warning(e, y);
+                                                }
+                                                Ok(i) => {
+                                                    clear_and_show(&state.current_dir);
+                                                    state.update_list();
+                                                    state.list_up(nums.skip);
+                                                    let mut restore_message = i.to_string();
+                                                    restore_message.push_str(" items restored");
+                                                    print_info(restore_message, y);
+                                                    state.move_cursor(&nums, y);
+                                                }
+                                            }
+                                        }
+                                        break 'command;
+                                    }
+
+                                    if c == "du" && args.is_empty() {
+                                        usage_mode(&mut state, &mut screen, &mut stdin);
+                                        clear_and_show(&state.current_dir);
+                                        state.update_list();
+                                        state.list_up(nums.skip);
+                                        print!("{}", cursor::Hide);
+                                        if state.list.is_empty() {
+                                            nums.reset();
+                                            state.move_cursor(&nums, STARTING_POINT);
+                                        } else if nums.index > state.list.len() - 1 {
+                                            nums.reset();
+                                            clear_and_show(&state.current_dir);
+                                            state.list_up(0);
+                                            state.move_cursor(&nums, STARTING_POINT);
+                                        } else {
+                                            state.move_cursor(&nums, y);
+                                        }
+                                        break 'command;
+                                    }
+
+                                    if c == "trash" && args.is_empty() {
+                                        trash_mode(&mut state, &mut screen, &mut stdin);
+                                        clear_and_show(&state.current_dir);
+                                        state.update_list();
+                                        state.list_up(nums.skip);
+                                        print!("{}", cursor::Hide);
+                                        if state.list.is_empty() {
+                                            nums.reset();
+                                            state.move_cursor(&nums, STARTING_POINT);
+                                        } else if nums.index > state.list.len() - 1 {
+                                            nums.reset();
+                                            clear_and_show(&state.current_dir);
+                                            state.list_up(0);
+                                            state.move_cursor(&nums, STARTING_POINT);
+                                        } else {
+                                            state.move_cursor(&nums, y);
+                                        }
+                                        break 'command;
+                                    }
+
+                                    if c == "rename-pattern" && !args.is_empty() {
+                                        print!("{}", cursor::Hide);
+                                        let pattern: String = command.iter().collect();
+                                        let pattern = pattern.trim_start()[c.len()..].trim();
+                                        //selected items, or the item under the cursor
+                                        let mut names: Vec<OsString> = state
+                                            .list
+                                            .iter()
+                                            .filter(|item| item.selected)
+                                            .map(|item| item.file_name.clone())
+                                            .collect();
+                                        if names.is_empty() {
+                                            if let Ok(item) = state.get_item(nums.index) {
+                                                names.push(item.file_name.clone());
+                                            }
+                                        }
+                                        let result = rename_by_pattern(
+                                            &mut state,
+                                            &mut screen,
+                                            &mut stdin,
+                                            names,
+                                            pattern,
+                                        );
+                                        clear_and_show(&state.current_dir);
+                                        state.update_list();
+                                        state.list_up(nums.skip);
+                                        match result {
+                                            Ok(message) => print_info(message, y),
+                                            Err(e) => print_warning(e, y),
+                                        }
+                                        state.move_cursor(&nums, y);
+                                        break 'command;
+                                    }
+
+                                    if c == "bulkrename" && args.is_empty() {
+                                        let names: Vec<OsString> = state
+                                            .list
+                                            .iter()
+                                            .map(|item| item.file_name.clone())
+                                            .collect();
+                                        let result = bulk_rename(&mut state, names);
+                                        clear_and_show(&state.current_dir);
+                                        state.update_list();
+                                        state.list_up(nums.skip);
+                                        print!("{}", cursor::Hide);
+                                        match result {
+                                            Ok(message) => print_info(message, y),
+                                            Err(e) => print_warning(e, y),
+                                        }
+                                        state.move_cursor(&nums, y);
+                                        break 'command;
+                                    }
+
+                                    if c == "purge" && args.is_empty() {
+                                        print!("{}", cursor::Hide);
+                                        print_info("Processing...", y);
+                                        screen.flush().unwrap();
+                                        match state.purge_trash() {
+                                            Err(e) => {
+                                                print_warning(e, y);
+                                            }
+                                            Ok(i) => {
+                                                let mut purge_message = i.to_string();
+                                                purge_message.push_str(" items purged");
+                                                if trash::is_files_dir(
+                                                    &state.trash_dir,
+                                                    &state.current_dir,
+                                                ) {
+                                                    clear_and_show(&state.current_dir);
+                                                    state.update_list();
+                                                    state.list_up(0);
+                                                    nums.reset();
+                                                    print_info(purge_message, STARTING_POINT);
+                                                    state.move_cursor(&nums, STARTING_POINT);
+                                                } else {
+                                                    print_info(purge_message, y);
+                                                }
+                                            }
+                                        }
+                                        break 'command;
+                                    }
+
+                                    if c == "empty" && args.is_empty() {
+                                        print_warning(WHEN_EMPTY, y);
+                                        screen.flush().unwrap();
+
+                                        'empty: loop {
+                                            let input = stdin.next();
+                                            if let Some(Ok(key)) = input {
+                                                match key {
+                                                    Key::Char('y') | Key::Char('Y') => {
+                                                        print_info("Processing...", y);
+                                                        screen.flush().unwrap();
+
+                                                        if let Err(e) =
+                                                            trash::empty(&state.trash_dir)
+                                                        {
+                                                            print!("{}", cursor::Hide);
+                                                            print_warning(e, y);
+                                                            continue 'main;
+                                                        }
+                                                        break 'empty;
+                                                    }
+                                                    _ => {
+                                                        break 'empty;
+                                                    }
                                                 }
                                             }
                                         }
@@ -1070,7 +1575,8 @@ pub fn run(arg: PathBuf) {
                                             clear::CurrentLine,
                                             DOWN_ARROW
                                         );
-                                        if state.current_dir == state.trash_dir {
+                                        if trash::is_files_dir(&state.trash_dir, &state.current_dir)
+                                        {
                                             clear_and_show(&state.current_dir);
                                             state.update_list();
                                             state.list_up(nums.skip);
@@ -1219,3 +1725,795 @@ pub fn run(arg: PathBuf) {
     //When finishes, restore the cursor
     print!("{}", cursor::Restore);
 }
+
+//Browse the trash with the original path, deletion time and size of each item.
+fn trash_mode<W: Write>(state: &mut State, screen: &mut W, stdin: &mut Input) {
+    let mut sort_key = TrashSortKey::Time;
+    let mut items = match trash::list_items(&state.trash_dir, sort_key) {
+        Ok(items) => items,
+        Err(e) => {
+            print_warning(e, STARTING_POINT);
+            return;
+        }
+    };
+    let mut index: usize = 0;
+    let mut skip: usize = 0;
+    let visible = (state.layout.terminal_row - STARTING_POINT) as usize;
+
+    state.list_up_trash(&items, index, skip);
+    screen.flush().unwrap();
+
+    loop {
+        let cursor_pos = (index - skip) as u16 + STARTING_POINT;
+        let input = stdin.next();
+        if let Some(Ok(key)) = input {
+            match key {
+                Key::Char('j') | Key::Down => {
+                    if items.is_empty() || index == items.len() - 1 {
+                        continue;
+                    }
+                    index += 1;
+                    if index >= skip + visible {
+                        skip += 1;
+                    }
+                }
+
+                Key::Char('k') | Key::Up => {
+                    if index == 0 {
+                        continue;
+                    }
+                    index -= 1;
+                    if index < skip {
+                        skip -= 1;
+                    }
+                }
+
+                Key::Char('g') => {
+                    if let Some(Ok(Key::Char('g'))) = stdin.next() {
+                        index = 0;
+                        skip = 0;
+                    }
+                }
+
+                Key::Char('G') => {
+                    if items.is_empty() {
+                        continue;
+                    }
+                    index = items.len() - 1;
+                    skip = items.len().saturating_sub(visible);
+                }
+
+                //Toggle sort order (name -> deletion time -> size)
+                Key::Char('t') => {
+                    sort_key = sort_key.next();
+                    trash::sort_items(&mut items, sort_key);
+                    index = 0;
+                    skip = 0;
+                    state.list_up_trash(&items, index, skip);
+                    print_info(format!("Sorted by {:?}", sort_key), STARTING_POINT);
+                    screen.flush().unwrap();
+                    continue;
+                }
+
+                //Restore the item to its original location
+                Key::Char('r') => {
+                    let trash_item = match items.get(index) {
+                        Some(trash_item) => trash_item.clone(),
+                        None => continue,
+                    };
+                    let message = match state.restore_item(&trash_item.item) {
+                        Ok(to) => format!("Restored to {}", escape_name(to.as_os_str())),
+                        Err(e) => {
+                            print_warning(e, cursor_pos);
+                            screen.flush().unwrap();
+                            continue;
+                        }
+                    };
+                    items.remove(index);
+                    if index > 0 && index == items.len() {
+                        index -= 1;
+                    }
+                    skip = skip.min(index);
+                    state.list_up_trash(&items, index, skip);
+                    print_info(message, (index - skip) as u16 + STARTING_POINT);
+                    screen.flush().unwrap();
+                    continue;
+                }
+
+                //Delete the item permanently
+                Key::Char('d') => {
+                    let trash_item = match items.get(index) {
+                        Some(trash_item) => trash_item.clone(),
+                        None => continue,
+                    };
+                    print_warning(WHEN_DELETE, cursor_pos);
+                    screen.flush().unwrap();
+                    if let Some(Ok(Key::Char('y'))) | Some(Ok(Key::Char('Y'))) = stdin.next() {
+                        if let Err(e) = trash::delete(&trash_item) {
+                            state.list_up_trash(&items, index, skip);
+                            print_warning(e, cursor_pos);
+                            screen.flush().unwrap();
+                            continue;
+                        }
+                        items.remove(index);
+                        if index > 0 && index == items.len() {
+                            index -= 1;
+                        }
+                        skip = skip.min(index);
+                        state.list_up_trash(&items, index, skip);
+                        print_info(
+                            "1 item deleted permanently",
+                            (index - skip) as u16 + STARTING_POINT,
+                        );
+                        screen.flush().unwrap();
+                        continue;
+                    }
+                }
+
+                Key::Esc | Key::Char('q') | Key::Char('h') | Key::Left => {
+                    break;
+                }
+
+                _ => {
+                    continue;
+                }
+            }
+            state.list_up_trash(&items, index, skip);
+            screen.flush().unwrap();
+        }
+    }
+}
+
+//Show the current directory's subtree sorted by size, to find what takes up the disk.
+//Items are deleted with dd in the same way as in the normal list.
+fn usage_mode<W: Write>(state: &mut State, screen: &mut W, stdin: &mut Input) {
+    print_info("Scanning...", STARTING_POINT);
+    screen.flush().unwrap();
+    let root = state.current_dir.clone();
+    let mut usage = match run_in_background(state, screen, stdin, STARTING_POINT, move |ops| {
+        Usage::scan(&root, &ops.progress)
+    }) {
+        Ok(usage) => usage,
+        Err(e) => {
+            print_warning(e, STARTING_POINT);
+            screen.flush().unwrap();
+            return;
+        }
+    };
+
+    let mut dir = usage.root.clone();
+    let mut items = usage.list(&dir).unwrap_or_default();
+    //cursor positions in the parent directories
+    let mut parents: Vec<(usize, usize)> = Vec::new();
+    let mut index: usize = 0;
+    let mut skip: usize = 0;
+    let visible = (state.layout.terminal_row - STARTING_POINT) as usize;
+
+    state.list_up_usage(&dir, &items, usage.size(&dir), index, skip);
+    screen.flush().unwrap();
+
+    loop {
+        let cursor_pos = (index - skip) as u16 + STARTING_POINT;
+        let input = stdin.next();
+        if let Some(Ok(key)) = input {
+            match key {
+                Key::Char('j') | Key::Down => {
+                    if items.is_empty() || index == items.len() - 1 {
+                        continue;
+                    }
+                    index += 1;
+                    if index >= skip + visible {
+                        skip += 1;
+                    }
+                }
+
+                Key::Char('k') | Key::Up => {
+                    if index == 0 {
+                        continue;
+                    }
+                    index -= 1;
+                    if index < skip {
+                        skip -= 1;
+                    }
+                }
+
+                Key::Char('g') => {
+                    if let Some(Ok(Key::Char('g'))) = stdin.next() {
+                        index = 0;
+                        skip = 0;
+                    }
+                }
+
+                Key::Char('G') => {
+                    if items.is_empty() {
+                        continue;
+                    }
+                    index = items.len() - 1;
+                    skip = items.len().saturating_sub(visible);
+                }
+
+                //Drill down into the directory
+                Key::Char('l') | Key::Char('\n') | Key::Right => {
+                    let usage_item = match items.get(index) {
+                        Some(usage_item) if usage_item.item.file_type == FileType::Directory => {
+                            usage_item
+                        }
+                        _ => continue,
+                    };
+                    match usage.list(&usage_item.item.file_path) {
+                        Ok(new_items) => {
+                            dir = usage_item.item.file_path.clone();
+                            items = new_items;
+                            parents.push((index, skip));
+                            index = 0;
+                            skip = 0;
+                        }
+                        Err(e) => {
+                            print_warning(e, cursor_pos);
+                            screen.flush().unwrap();
+                            continue;
+                        }
+                    }
+                }
+
+                //Go back up, until the directory where the scan started
+                Key::Char('h') | Key::Left => {
+                    let (parent_index, parent_skip) = match parents.pop() {
+                        Some(position) => position,
+                        None => continue,
+                    };
+                    dir.pop();
+                    items = usage.list(&dir).unwrap_or_default();
+                    index = parent_index.min(items.len().saturating_sub(1));
+                    skip = parent_skip.min(index);
+                }
+
+                //Delete and yank the item
+                Key::Char('d') => {
+                    let usage_item = match items.get(index) {
+                        Some(usage_item) => usage_item.clone(),
+                        None => continue,
+                    };
+                    print!("{}{}d", cursor::Goto(2, 2), clear::CurrentLine);
+                    screen.flush().unwrap();
+                    if let Some(Ok(Key::Char('d'))) = stdin.next() {
+                        print_info("Processing...", cursor_pos);
+                        screen.flush().unwrap();
+                        let item = usage_item.item.clone();
+                        if let Err(e) =
+                            run_in_background(state, screen, stdin, cursor_pos, move |ops| {
+                                ops.remove_and_yank_selected(vec![item])
+                            })
+                        {
+                            state.list_up_usage(&dir, &items, usage.size(&dir), index, skip);
+                            print_warning(e, cursor_pos);
+                            screen.flush().unwrap();
+                            continue;
+                        }
+                        usage.remove(&usage_item);
+                        items = usage.list(&dir).unwrap_or_default();
+                        if index > 0 && index >= items.len() {
+                            index = items.len().saturating_sub(1);
+                        }
+                        skip = skip.min(index);
+                        state.list_up_usage(&dir, &items, usage.size(&dir), index, skip);
+                        print_info(
+                            format!(
+                                "1 item deleted ({})",
+                                format_size(usage_item.size, &state.format)
+                            ),
+                            (index - skip) as u16 + STARTING_POINT,
+                        );
+                        screen.flush().unwrap();
+                        continue;
+                    }
+                }
+
+                Key::Esc | Key::Char('q') => {
+                    break;
+                }
+
+                _ => {
+                    continue;
+                }
+            }
+            state.list_up_usage(&dir, &items, usage.size(&dir), index, skip);
+            screen.flush().unwrap();
+        }
+    }
+}
+
+//What the worker gives back: the file operations with registered items and journal updated.
+struct Finished<T> {
+    ops: FileOps,
+    result: std::io::Result<T>,
+}
+
+//Run the heavy file operation on a worker thread, showing its progress in the status line.
+//Esc cancels the operation.
+fn run_in_background<W, F, T>(
+    state: &mut State,
+    screen: &mut W,
+    stdin: &mut Input,
+    y: u16,
+    f: F,
+) -> std::io::Result<T>
+where
+    W: Write,
+    F: FnOnce(&mut FileOps) -> std::io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    state.progress.reset();
+    let progress = state.progress.clone();
+    let mut ops = state.file_ops();
+    let start = Instant::now();
+    let handle = std::thread::spawn(move || {
+        let result = f(&mut ops);
+        Finished { ops, result }
+    });
+
+    while !handle.is_finished() {
+        let mut status = progress.format(start.elapsed(), &state.format);
+        status.push_str(" (Esc to cancel)");
+        let status: String = status
+            .chars()
+            .take(state.layout.terminal_column as usize)
+            .collect();
+        print!(
+            "{}{}{}{}>{}",
+            cursor::Goto(1, state.layout.terminal_row),
+            clear::CurrentLine,
+            status,
+            cursor::Goto(1, y),
+            cursor::Left(1)
+        );
+        screen.flush().unwrap();
+
+        if stdin.wait(Duration::from_millis(100)) {
+            if let Some(Ok(Key::Esc)) = stdin.next() {
+                progress.cancel();
+            }
+        }
+    }
+
+    match handle.join() {
+        Ok(finished) => {
+            state.finish_file_ops(finished.ops);
+            finished.result
+        }
+        Err(_) => Err(std::io::Error::other("file operation panicked.")),
+    }
+}
+
+//Redraw the list as directory sizes arrive, until a key is pressed.
+//When sorted by size, the list is sorted again after all sizes have arrived.
+fn show_dir_sizes(state: &mut State, stdin: &mut Input, nums: &mut Num, shown: &mut usize) {
+    loop {
+        let busy = state.dir_sizes.is_busy();
+        let generation = state.dir_sizes.generation();
+        if generation != *shown {
+            *shown = generation;
+            if !busy && matches!(state.sort_by, SortKey::Size) {
+                if let Ok(item) = state.get_item(nums.index) {
+                    let name = item.file_name.clone();
+                    state.sort_by_dir_size();
+                    move_to_item(state, nums, name.as_ref());
+                }
+            } else {
+                clear_and_show(&state.current_dir);
+                state.list_up(nums.skip);
+                state.move_cursor(
+                    nums,
+                    (nums.index - nums.skip as usize) as u16 + STARTING_POINT,
+                );
+            }
+            std::io::stdout().flush().unwrap();
+        }
+        if !busy || stdin.wait(Duration::from_millis(100)) {
+            break;
+        }
+    }
+}
+
+//Decide how to put each registered item, asking what to do when its name already exists.
+//Returns None if cancelled with Esc.
+fn make_put_plan<W: Write>(
+    state: &State,
+    screen: &mut W,
+    stdin: &mut Input,
+    y: u16,
+) -> Option<Vec<(ItemInfo, PutAction)>> {
+    let mut name_set = make_name_set(&state.current_dir).unwrap_or_default();
+    let mut policy = state.on_conflict;
+    let mut plan = Vec::new();
+
+    for item in state.registered.iter() {
+        let name = state.put_name(item);
+        let action = if !name_set.contains(&name)
+            || (state.cut && item.file_path == state.current_dir.join(&name))
+        {
+            PutAction::Rename(name)
+        } else if policy == ConflictPolicy::Ask {
+            let (choice, to_all) = ask_conflict(state, screen, stdin, item, &name, y)?;
+            if to_all {
+                policy = choice;
+            }
+            state.resolve_conflict(item, choice, &name_set)
+        } else {
+            state.resolve_conflict(item, policy, &name_set)
+        };
+
+        if let PutAction::Rename(name) = &action {
+            name_set.insert(name.clone());
+        }
+        plan.push((item.clone(), action));
+    }
+    Some(plan)
+}
+
+//Returns the choice and whether to apply it to all the following conflicts.
+fn ask_conflict<W: Write>(
+    state: &State,
+    screen: &mut W,
+    stdin: &mut Input,
+    item: &ItemInfo,
+    name: &OsStr,
+    y: u16,
+) -> Option<(ConflictPolicy, bool)> {
+    let overwrite = if item.file_type == FileType::Directory {
+        "[o]verwrite(merge)"
+    } else {
+        "[o]verwrite"
+    };
+    let question = format!(
+        "{} exists: {} [s]kip [r]ename [c]ompare (O/S/R: apply to all)",
+        escape_name(name),
+        overwrite
+    );
+
+    loop {
+        print_warning(&question, y);
+        screen.flush().unwrap();
+        match stdin.next() {
+            Some(Ok(Key::Char('o'))) => return Some((ConflictPolicy::Overwrite, false)),
+            Some(Ok(Key::Char('O'))) => return Some((ConflictPolicy::Overwrite, true)),
+            Some(Ok(Key::Char('s'))) => return Some((ConflictPolicy::Skip, false)),
+            Some(Ok(Key::Char('S'))) => return Some((ConflictPolicy::Skip, true)),
+            Some(Ok(Key::Char('r'))) => return Some((ConflictPolicy::Rename, false)),
+            Some(Ok(Key::Char('R'))) => return Some((ConflictPolicy::Rename, true)),
+            Some(Ok(Key::Char('c'))) => {
+                print_info(
+                    format!(
+                        "existing: {} / new: {} (any key to go back)",
+                        describe_item(&state.current_dir.join(name), &state.format),
+                        describe_item(&item.file_path, &state.format)
+                    ),
+                    y,
+                );
+                screen.flush().unwrap();
+                let _ = stdin.next();
+            }
+            Some(Ok(Key::Esc)) => return None,
+            _ => continue,
+        }
+    }
+}
+
+//Rename items by editing their names in the editor.
+fn bulk_rename(state: &mut State, names: Vec<OsString>) -> std::io::Result<String> {
+    if names.is_empty() {
+        return Ok("No items to rename".to_string());
+    }
+    let lossy_names: Vec<String> = names
+        .iter()
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect();
+    print!("{}", screen::ToAlternateScreen);
+    let edited = edit_names(&state.editor(), &lossy_names);
+    print!("{}", screen::ToAlternateScreen);
+
+    //names not in UTF-8 are edited with U+FFFD in place of the invalid bytes,
+    //so they are kept only if left as they are
+    let mut renames = Vec::new();
+    for ((old, lossy), new) in names.into_iter().zip(lossy_names).zip(edited?) {
+        if old.to_str().is_some() || !new.contains(char::REPLACEMENT_CHARACTER) {
+            renames.push((old, OsString::from(new)));
+        } else if new == lossy {
+            renames.push((old.clone(), old));
+        } else {
+            return Err(std::io::Error::other(format!(
+                "cannot rename {}: name not in UTF-8.",
+                escape_name(&old)
+            )));
+        }
+    }
+    let (count, staged) = state.rename_items(&renames)?;
+    if staged == 0 {
+        Ok(format!("{} items renamed", count))
+    } else {
+        Ok(format!(
+            "{} items renamed ({} via temporary names to swap)",
+            count, staged
+        ))
+    }
+}
+
+//Read a line on the second row. Returns None if cancelled with Esc.
+fn read_line<W: Write>(
+    screen: &mut W,
+    stdin: &mut Input,
+    prompt: &str,
+    initial: &str,
+) -> Option<String> {
+    let mut line: Vec<char> = initial.chars().collect();
+    let mut pos = line.len();
+    loop {
+        print!(
+            "{}{}{}{}{}",
+            cursor::Goto(2, 2),
+            clear::CurrentLine,
+            prompt,
+            line.iter().collect::<String>(),
+            cursor::Goto(
+                (2 + prompt.width() + line[..pos].iter().collect::<String>().width()) as u16,
+                2
+            )
+        );
+        screen.flush().unwrap();
+
+        match stdin.next() {
+            Some(Ok(Key::Char('\n'))) => return Some(line.iter().collect()),
+            Some(Ok(Key::Esc)) => return None,
+            Some(Ok(Key::Left)) => pos = pos.saturating_sub(1),
+            Some(Ok(Key::Right)) => pos = (pos + 1).min(line.len()),
+            Some(Ok(Key::Backspace)) => {
+                if pos > 0 {
+                    pos -= 1;
+                    line.remove(pos);
+                }
+            }
+            Some(Ok(Key::Char(c))) => {
+                line.insert(pos, c);
+                pos += 1;
+            }
+            _ => continue,
+        }
+    }
+}
+
+//Rename items by regex or template, after showing the preview and asking for confirmation.
+fn rename_by_pattern<W: Write>(
+    state: &mut State,
+    screen: &mut W,
+    stdin: &mut Input,
+    names: Vec<OsString>,
+    pattern: &str,
+) -> std::io::Result<String> {
+    let new_names = apply_pattern(pattern, &names)?;
+    let names: Vec<(OsString, OsString)> = names.into_iter().zip(new_names).collect();
+    let changed: Vec<(String, String)> = names
+        .iter()
+        .filter(|(old, new)| old != new)
+        .map(|(old, new)| (escape_name(old), escape_name(new)))
+        .collect();
+    if changed.is_empty() {
+        return Ok("No items to rename".to_string());
+    }
+
+    //preview: `old -> new` for each item to be renamed
+    clear_and_show(&state.current_dir);
+    let rows = state.layout.terminal_row.saturating_sub(STARTING_POINT) as usize;
+    let old_width = changed
+        .iter()
+        .map(|(old, _)| old.width())
+        .max()
+        .unwrap_or(0);
+    for (i, (old, new)) in changed.iter().take(rows).enumerate() {
+        let line = if i == rows - 1 && changed.len() > rows {
+            format!("... and {} more", changed.len() - i)
+        } else {
+            format!("{} -> {}", pad_to_width(old, old_width), new)
+        };
+        let line = truncate_to_width(&line, state.layout.terminal_column as usize - 3);
+        print!("{}{}", cursor::Goto(3, STARTING_POINT + i as u16), line);
+    }
+    print!(
+        "{}{}Rename {} items? [y/N]",
+        cursor::Goto(2, 2),
+        clear::CurrentLine,
+        changed.len()
+    );
+    screen.flush().unwrap();
+
+    match stdin.next() {
+        Some(Ok(Key::Char('y'))) | Some(Ok(Key::Char('Y'))) => {
+            let (count, _) = state.rename_items(&names)?;
+            Ok(format!("{} items renamed", count))
+        }
+        _ => Ok("Rename cancelled".to_string()),
+    }
+}
+
+//Redraw the list with the cursor on the item with the name.
+fn move_to_item(state: &State, nums: &mut Num, name: &OsStr) {
+    let mut new_pos = 0;
+    for (i, item) in state.list.iter().enumerate() {
+        let name_as_os_str: &OsStr = item.file_name.as_ref();
+        if name_as_os_str == name {
+            new_pos = i;
+        }
+    }
+    nums.index = new_pos;
+
+    if nums.index >= (state.layout.terminal_row - (STARTING_POINT + 3)).into() {
+        nums.skip = (nums.index - 3) as u16;
+        clear_and_show(&state.current_dir);
+        state.list_up(nums.skip);
+        state.move_cursor(nums, STARTING_POINT + 3);
+    } else {
+        nums.skip = 0;
+        clear_and_show(&state.current_dir);
+        state.list_up(0);
+        state.move_cursor(nums, (nums.index + 3) as u16);
+    }
+}
+
+//Choose a file in the templates directory with j/k and Enter. Esc returns None.
+fn pick_template<W: Write>(
+    state: &State,
+    screen: &mut W,
+    stdin: &mut Input,
+) -> std::io::Result<Option<PathBuf>> {
+    let templates = state.list_templates().unwrap_or_default();
+    if templates.is_empty() {
+        return Err(std::io::Error::other(format!(
+            "No templates in {}",
+            escape_name(state.templates_dir.as_os_str())
+        )));
+    }
+    let visible = (state.layout.terminal_row - STARTING_POINT) as usize;
+    let mut index = 0;
+    let mut skip = 0;
+
+    loop {
+        clear_and_show(&state.templates_dir);
+        for (i, template) in templates.iter().enumerate().skip(skip).take(visible) {
+            let name = truncate_to_width(
+                &template.file_name().map(escape_name).unwrap_or_default(),
+                state.layout.terminal_column as usize - 3,
+            );
+            print!(
+                "{}{}",
+                cursor::Goto(3, (i - skip) as u16 + STARTING_POINT),
+                name
+            );
+        }
+        print!(
+            "{}{}[{}/{}] Enter: choose, Esc: cancel",
+            cursor::Goto(1, state.layout.terminal_row),
+            clear::CurrentLine,
+            index + 1,
+            templates.len()
+        );
+        print!(
+            "{}>{}",
+            cursor::Goto(1, (index - skip) as u16 + STARTING_POINT),
+            cursor::Left(1)
+        );
+        screen.flush().unwrap();
+
+        match stdin.next() {
+            Some(Ok(Key::Char('j'))) | Some(Ok(Key::Down)) => {
+                if index < templates.len() - 1 {
+                    index += 1;
+                    if index >= skip + visible {
+                        skip += 1;
+                    }
+                }
+            }
+            Some(Ok(Key::Char('k'))) | Some(Ok(Key::Up)) => {
+                if index > 0 {
+                    index -= 1;
+                    if index < skip {
+                        skip -= 1;
+                    }
+                }
+            }
+            Some(Ok(Key::Char('\n'))) | Some(Ok(Key::Char('l'))) | Some(Ok(Key::Right)) => {
+                return Ok(Some(templates[index].clone()));
+            }
+            Some(Ok(Key::Esc)) | Some(Ok(Key::Char('q'))) | Some(Ok(Key::Char('h'))) => {
+                return Ok(None);
+            }
+            _ => continue,
+        }
+    }
+}
+
+//Toggle permission bits of the items in the dialog, then apply them.
+//A bit shown as ? differs among the items, and only the toggled bits are changed.
+fn chmod<W: Write>(
+    state: &mut State,
+    screen: &mut W,
+    stdin: &mut Input,
+    items: &[ItemInfo],
+) -> std::io::Result<String> {
+    if items.is_empty() {
+        return Ok("No items to chmod".to_string());
+    }
+    let has_dir = items
+        .iter()
+        .any(|item| item.file_type == FileType::Directory);
+    let all_on = items
+        .iter()
+        .fold(0o777, |bits, item| bits & item.permissions);
+    let any_on = items.iter().fold(0, |bits, item| bits | item.permissions) & 0o777;
+    let mut change = ModeChange::default();
+    let mut pos: u16 = 0;
+
+    print!("{}", cursor::Show);
+    loop {
+        let shown = (all_on | change.set) & !change.clear;
+        let mut bits = String::new();
+        for (i, c) in "rwxrwxrwx".chars().enumerate() {
+            let bit = 0o400 >> i;
+            if shown & bit != 0 {
+                bits.push(c);
+            } else if any_on & !change.clear & bit != 0 {
+                bits.push('?');
+            } else {
+                bits.push('-');
+            }
+        }
+        let recursive_label = match (has_dir, change.recursive) {
+            (false, _) => "".to_string(),
+            (true, false) => " R: recursive(off)".to_string(),
+            (true, true) => format!(
+                " R: recursive(on) X: x for files({})",
+                if change.file_x { "on" } else { "off" }
+            ),
+        };
+        print!(
+            "{}{}chmod: {} Space: toggle{} Enter: apply Esc: cancel{}",
+            cursor::Goto(2, 2),
+            clear::CurrentLine,
+            bits,
+            recursive_label,
+            cursor::Goto(9 + pos, 2)
+        );
+        screen.flush().unwrap();
+
+        match stdin.next() {
+            Some(Ok(Key::Char('h'))) | Some(Ok(Key::Left)) => pos = pos.saturating_sub(1),
+            Some(Ok(Key::Char('l'))) | Some(Ok(Key::Right)) => pos = (pos + 1).min(8),
+            Some(Ok(Key::Char(' '))) => {
+                let bit = 0o400 >> pos;
+                if shown & bit != 0 {
+                    change.set &= !bit;
+                    change.clear |= bit;
+                } else {
+                    change.clear &= !bit;
+                    change.set |= bit;
+                }
+            }
+            Some(Ok(Key::Char('R'))) if has_dir => change.recursive = !change.recursive,
+            Some(Ok(Key::Char('X'))) if change.recursive => change.file_x = !change.file_x,
+            Some(Ok(Key::Char('\n'))) => break,
+            Some(Ok(Key::Esc)) => {
+                print!("{}", cursor::Hide);
+                return Ok("Chmod cancelled".to_string());
+            }
+            _ => continue,
+        }
+    }
+    print!("{}", cursor::Hide);
+
+    let paths: Vec<PathBuf> = items.iter().map(|item| item.file_path.clone()).collect();
+    let count = state.chmod_items(&paths, &change)?;
+    Ok(format!(
+        "{} items changed (on: {}, off: {})",
+        count,
+        format_permissions(change.set),
+        format_permissions(change.clear)
+    ))
+}
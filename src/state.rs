@@ -1,82 +1,121 @@
 use super::config::*;
+use super::dirsize::DirSizes;
 use super::functions::*;
+use super::journal::{Journal, Operation};
 use super::nums::*;
+use super::progress::Progress;
+use super::trash::{self, TrashItem};
+use super::usage::UsageItem;
 use chrono::prelude::*;
 use log::debug;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::ffi::OsString;
 use std::fs;
-use std::io::{Error, ErrorKind};
-use std::path::{Path, PathBuf};
+use std::io::{Error, ErrorKind, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
 use std::process::{Command, ExitStatus};
+use std::sync::Arc;
 use termion::{clear, color, cursor, style};
+use unicode_width::UnicodeWidthStr;
+use users::{get_group_by_gid, get_user_by_uid};
 
 pub const STARTING_POINT: u16 = 3;
 pub const DOWN_ARROW: char = '\u{21D3}';
 pub const RIGHT_ARROW: char = '\u{21D2}';
 pub const FX_CONFIG_DIR: &str = "felix";
 pub const CONFIG_FILE: &str = "config.toml";
-pub const TRASH: &str = "trash";
+pub const TEMPLATES: &str = "templates";
+const MIN_NAME_LEN: usize = 16;
+const TRASH_NAME_LEN: usize = 29;
+const USAGE_BAR_WIDTH: usize = 10;
 pub const WHEN_EMPTY: &str = "Are you sure to empty the trash directory? (if yes: y)";
+pub const WHEN_DELETE: &str = "Are you sure to delete this item permanently? (if yes: y)";
 
-macro_rules! print_item {
-    ($color: expr, $name: expr, $time: expr, $selected: expr, $time_start_pos: expr, $column: expr) => {
-        if *($selected) {
-            print!(
-                "{}{}{}{}{}{} {}{}{}",
-                $color,
-                style::Invert,
-                $name,
-                style::Reset,
-                cursor::Left(60),
-                cursor::Right($time_start_pos),
-                style::Invert,
-                $time,
-                style::Reset
-            );
-        } else {
-            print!(
-                "{}{}{}{} {}{}",
-                $color,
-                $name,
-                cursor::Left(60),
-                cursor::Right($time_start_pos),
-                $time,
-                color::Fg(color::Reset)
-            );
-        }
-        if $column > 58 {
-            print!("{}", (0..8).map(|_| ' ').collect::<String>());
-        } else if $column > 49 {
-            print!("{}", (0..($column - 49)).map(|_| ' ').collect::<String>());
-        }
-    };
-}
 #[derive(Clone)]
 pub struct State {
     pub list: Vec<ItemInfo>,
     pub registered: Vec<ItemInfo>,
+    pub cut: bool,
     pub current_dir: PathBuf,
     pub trash_dir: PathBuf,
+    pub trash_config: TrashConfig,
+    pub copy_config: CopyConfig,
     pub colors: (Colorname, Colorname, Colorname),
     pub default: String,
     pub commands: HashMap<String, String>,
     pub sort_by: SortKey,
+    pub on_conflict: ConflictPolicy,
+    pub open_new_file: bool,
+    pub templates_dir: PathBuf,
+    pub author: String,
     pub layout: Layout,
+    pub columns: Vec<Column>,
+    pub format: FormatConfig,
+    pub dir_sizes: DirSizes,
+    pub column_cache: ColumnCache,
+    pub progress: Arc<Progress>,
+    pub journal: Journal,
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct ItemInfo {
     pub file_type: FileType,
-    pub file_name: String,
+    pub file_name: OsString,
     pub file_path: std::path::PathBuf,
     pub file_size: u64,
     pub file_ext: Option<OsString>,
     pub modified: Option<String>,
+    pub changed: Option<String>,
+    pub permissions: u32,
+    pub uid: u32,
+    pub gid: u32,
+    //Read once when listed, as it is shown on every redraw.
+    pub link_target: Option<PathBuf>,
     pub selected: bool,
 }
 
+//How each registered item is put.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PutAction {
+    Rename(OsString),
+    Overwrite(OsString),
+    Merge(OsString),
+    Skip,
+}
+
+//How registered items are linked into the current directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    Symlink,
+    RelativeSymlink,
+    HardLink,
+}
+
+//Permission bits turned on and off in the chmod dialog.
+//The bits in neither mask are left as they are, so that items with different modes keep them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModeChange {
+    pub set: u32,
+    pub clear: u32,
+    pub recursive: bool,
+    //Whether files in directories get x bits too, which are otherwise set only on directories.
+    pub file_x: bool,
+}
+
+impl ModeChange {
+    pub fn apply(&self, mode: u32, in_dir_file: bool) -> u32 {
+        let set = if in_dir_file && !self.file_x {
+            self.set & !0o111
+        } else {
+            self.set
+        };
+        (mode | (set & 0o777)) & !(self.clear & 0o777)
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum FileType {
     Directory,
@@ -89,7 +128,92 @@ pub struct Layout {
     pub terminal_row: u16,
     pub terminal_column: u16,
     pub name_max_len: usize,
-    pub time_start_pos: u16,
+    pub columns: Vec<Column>,
+}
+
+impl Layout {
+    //The name takes the width left by the columns.
+    //If it gets narrower than MIN_NAME_LEN, columns are hidden from the right.
+    pub fn new(
+        terminal_column: u16,
+        terminal_row: u16,
+        columns: &[Column],
+        format: &FormatConfig,
+    ) -> Self {
+        //rows start at the 3rd column, and the last one is left blank
+        let width = (terminal_column as usize).saturating_sub(3);
+        let mut columns = columns.to_vec();
+        let name_max_len = loop {
+            let columns_width: usize = columns
+                .iter()
+                .map(|column| column.width(format) as usize + 1)
+                .sum();
+            match width.checked_sub(columns_width) {
+                Some(name_max_len) if name_max_len >= MIN_NAME_LEN || columns.is_empty() => {
+                    break name_max_len;
+                }
+                _ => {
+                    columns.pop();
+                }
+            }
+        };
+        Layout {
+            terminal_row,
+            terminal_column,
+            name_max_len,
+            columns,
+        }
+    }
+}
+
+//Owner and group names and numbers of items in directories,
+//looked up when the list is updated instead of on every redraw.
+#[derive(Debug, Clone, Default)]
+pub struct ColumnCache {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+    items: HashMap<PathBuf, usize>,
+}
+
+impl ColumnCache {
+    fn update(&mut self, list: &[ItemInfo], columns: &[Column]) {
+        self.items.clear();
+        for column in columns.iter() {
+            match column.kind {
+                ColumnKind::Owner => {
+                    for item in list.iter() {
+                        self.users.entry(item.uid).or_insert_with(|| {
+                            match get_user_by_uid(item.uid) {
+                                Some(user) => escape_name(user.name()),
+                                None => item.uid.to_string(),
+                            }
+                        });
+                    }
+                }
+                ColumnKind::Group => {
+                    for item in list.iter() {
+                        self.groups.entry(item.gid).or_insert_with(|| {
+                            match get_group_by_gid(item.gid) {
+                                Some(group) => escape_name(group.name()),
+                                None => item.gid.to_string(),
+                            }
+                        });
+                    }
+                }
+                ColumnKind::Items => {
+                    for item in list
+                        .iter()
+                        .filter(|item| item.file_type == FileType::Directory)
+                    {
+                        if let Ok(entries) = fs::read_dir(&item.file_path) {
+                            self.items.insert(item.file_path.clone(), entries.count());
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
 }
 
 impl Default for State {
@@ -98,8 +222,11 @@ impl Default for State {
         State {
             list: Vec::new(),
             registered: Vec::new(),
+            cut: false,
             current_dir: PathBuf::new(),
             trash_dir: PathBuf::new(),
+            trash_config: config.trash,
+            copy_config: config.copy,
             colors: (
                 config.color.dir_fg,
                 config.color.file_fg,
@@ -108,12 +235,20 @@ impl Default for State {
             default: config.default,
             commands: to_extension_map(&config.exec),
             sort_by: config.sort_by,
-            layout: Layout {
-                terminal_row: 0,
-                terminal_column: 0,
-                name_max_len: 0,
-                time_start_pos: 0,
-            },
+            on_conflict: config.on_conflict,
+            open_new_file: config.open_new_file,
+            templates_dir: PathBuf::new(),
+            author: config
+                .author
+                .or_else(|| std::env::var("USER").ok())
+                .unwrap_or_default(),
+            layout: Layout::new(0, 0, &[], &FormatConfig::default()),
+            columns: config.columns,
+            format: config.format,
+            dir_sizes: DirSizes::default(),
+            column_cache: ColumnCache::default(),
+            progress: Arc::new(Progress::new()),
+            journal: Journal::default(),
         }
     }
 }
@@ -128,118 +263,349 @@ impl State {
             .ok_or_else(|| Error::new(ErrorKind::NotFound, "cannot choose item."))
     }
 
+    fn get_command(&self, path: &Path) -> String {
+        match path.extension() {
+            Some(extention) => {
+                let ext = extention.to_os_string().into_string().unwrap_or_default();
+                match self.commands.get(&ext) {
+                    Some(command) => command.clone(),
+                    None => self.default.clone(),
+                }
+            }
+            None => self.default.clone(),
+        }
+    }
+
     pub fn open_file(&self, index: usize) -> std::io::Result<ExitStatus> {
         let item = self.get_item(index)?;
         let path = &item.file_path;
-        let map = &self.commands;
-        let extention = path.extension();
+        let command = self.get_command(path);
+        Command::new(command).arg(path).status()
+    }
 
-        match extention {
-            Some(extention) => {
-                let ext = extention.to_os_string().into_string().unwrap();
-                match map.get(&ext) {
-                    Some(command) => {
-                        let mut ex = Command::new(command);
-                        ex.arg(path).status()
-                    }
-                    None => {
-                        let mut ex = Command::new(&self.default);
-                        ex.arg(path).status()
-                    }
-                }
+    //$EDITOR, or the default command in the config.
+    pub fn editor(&self) -> String {
+        std::env::var("EDITOR").unwrap_or_else(|_| self.default.clone())
+    }
+
+    pub fn open_selected(&self) -> std::io::Result<usize> {
+        //group selected files by command, keeping the order of the list
+        let mut groups: Vec<(String, Vec<&Path>)> = Vec::new();
+        for item in self.list.iter().filter(|item| item.selected) {
+            if item.file_type == FileType::Directory {
+                continue;
+            }
+            let command = self.get_command(&item.file_path);
+            match groups.iter_mut().find(|(c, _)| c == &command) {
+                Some((_, paths)) => paths.push(&item.file_path),
+                None => groups.push((command, vec![&item.file_path])),
             }
+        }
 
-            None => {
-                let mut ex = Command::new(&self.default);
-                ex.arg(path).status()
+        let mut count = 0;
+        for (i, (command, paths)) in groups.iter().enumerate() {
+            if let Err(e) = Command::new(command).args(paths).status() {
+                return Err(Error::new(
+                    e.kind(),
+                    format!(
+                        "cannot open with {}: {} ({} of {} groups opened, {} items)",
+                        command,
+                        e,
+                        i,
+                        groups.len(),
+                        count
+                    ),
+                ));
             }
+            count += paths.len();
         }
+        Ok(count)
     }
 
-    pub fn remove_and_yank_file(&mut self, item: ItemInfo) -> std::io::Result<()> {
-        //prepare from and to for copy
-        let from = &item.file_path;
-
-        if item.file_type == FileType::Symlink && !from.exists() {
-            let _ = Command::new("rm").arg(from).status();
-            Ok(())
-        } else {
-            let name = &item.file_name;
-            let mut rename = Local::now().timestamp().to_string();
-            rename.push('_');
-            rename.push_str(name);
-
-            let to = self.trash_dir.join(&rename);
+    //Rename with `c`.
+    pub fn rename_item(&mut self, from: &Path, to: &Path) -> std::io::Result<()> {
+        let result = self.journal_rename(from, to);
+        self.journal.commit("rename");
+        result
+    }
 
-            //copy
-            std::fs::copy(from, &to)?;
+    //Rename items in the current directory at once, given (old name, new name) of each.
+    //When a new name is the old name of another item (swap or cycle),
+    //the item goes through a temporary name first.
+    //Returns the number of renamed items and of those renamed via temporary names.
+    //Names not valid UTF-8 are edited lossily, so they are kept unless the new name differs.
+    pub fn rename_items(
+        &mut self,
+        names: &[(OsString, OsString)],
+    ) -> std::io::Result<(usize, usize)> {
+        let mut new_names = HashSet::new();
+        for (_, new) in names.iter() {
+            if new.is_empty() || new == "." || new == ".." || new.as_bytes().contains(&b'/') {
+                return Err(Error::other(format!(
+                    "invalid name: \"{}\"",
+                    escape_name(new)
+                )));
+            }
+            if !new_names.insert(new) {
+                return Err(Error::other(format!(
+                    "duplicate name: {}",
+                    escape_name(new)
+                )));
+            }
+        }
 
-            self.to_registered_mut(&item, to, rename);
+        let changed: Vec<(OsString, OsString)> = names
+            .iter()
+            .filter(|(old, new)| old != new)
+            .cloned()
+            .collect();
+        let old_names: HashSet<&OsString> = changed.iter().map(|(old, _)| old).collect();
+        for (_, new) in changed.iter() {
+            if !old_names.contains(new) && fs::symlink_metadata(self.current_dir.join(new)).is_ok()
+            {
+                return Err(Error::other(format!(
+                    "{} already exists.",
+                    escape_name(new)
+                )));
+            }
+        }
 
-            //remove original
-            std::fs::remove_file(from)?;
+        let result = self.rename_changed(&changed, &old_names);
+        self.journal.commit("rename");
+        result
+    }
 
-            Ok(())
+    fn rename_changed(
+        &mut self,
+        changed: &[(OsString, OsString)],
+        old_names: &HashSet<&OsString>,
+    ) -> std::io::Result<(usize, usize)> {
+        let mut staged = Vec::new();
+        let mut direct = Vec::new();
+        for (i, (old, new)) in changed.iter().enumerate() {
+            let from = self.current_dir.join(old);
+            let to = self.current_dir.join(new);
+            if old_names.contains(new) {
+                let temp =
+                    self.current_dir
+                        .join(format!(".felix_rename_{}_{}", std::process::id(), i));
+                self.journal_rename(&from, &temp)?;
+                staged.push((temp, to));
+            } else {
+                direct.push((from, to));
+            }
         }
+        for (from, to) in direct.iter().chain(staged.iter()) {
+            self.journal_rename(from, to)?;
+        }
+        Ok((changed.len(), staged.len()))
     }
 
-    pub fn remove_and_yank_dir(&mut self, item: ItemInfo) -> std::io::Result<()> {
-        let mut trash_name = String::new();
-        let mut base: usize = 0;
-        let mut trash_path: std::path::PathBuf = PathBuf::new();
-        let mut target: PathBuf;
+    fn journal_rename(&mut self, from: &Path, to: &Path) -> std::io::Result<()> {
+        fs::rename(from, to)?;
+        self.journal.record(Operation::Move {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        });
+        Ok(())
+    }
 
-        let mut i = 0;
-        for entry in walkdir::WalkDir::new(&item.file_path).sort_by_key(|x| x.path().to_path_buf())
-        {
-            let entry = entry?;
-            if i == 0 {
-                base = entry.path().iter().count();
+    //Make the directory with its parents like `mkdir -p`, or the new file.
+    //Returns the path of the item that appears in the current directory.
+    pub fn make_item(&mut self, path: &str, is_dir: bool) -> std::io::Result<PathBuf> {
+        let result = self.make_path(path, is_dir);
+        self.journal
+            .commit(if is_dir { "mkdir" } else { "new file" });
+        result
+    }
 
-                trash_name = chrono::Local::now().timestamp().to_string();
-                trash_name.push('_');
-                trash_name.push_str(entry.file_name().to_str().unwrap());
-                trash_path = self.trash_dir.join(&trash_name);
-                std::fs::create_dir(&self.trash_dir.join(&trash_path))?;
+    fn make_path(&mut self, path: &str, is_dir: bool) -> std::io::Result<PathBuf> {
+        let names = Path::new(path)
+            .components()
+            .map(|component| match component {
+                Component::Normal(name) => Ok(name),
+                _ => Err(Error::other("invalid name.")),
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let top = match names.first() {
+            Some(name) => self.current_dir.join(name),
+            None => return Err(Error::other("invalid name.")),
+        };
 
-                i += 1;
+        let mut target = self.current_dir.clone();
+        for (i, name) in names.iter().enumerate() {
+            target.push(name);
+            let is_last = i == names.len() - 1;
+            let is_file = is_last && !is_dir;
+            if is_file {
+                fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&target)?;
+            } else if target.is_dir() {
+                if is_last {
+                    return Err(Error::new(
+                        ErrorKind::AlreadyExists,
+                        format!("{} already exists.", path),
+                    ));
+                }
                 continue;
             } else {
-                target = entry.path().iter().skip(base).collect();
-                target = trash_path.join(target);
-                if entry.file_type().is_dir() {
-                    std::fs::create_dir(&target)?;
-                    continue;
-                }
-
-                if let Some(parent) = entry.path().parent() {
-                    if !parent.exists() {
-                        std::fs::create_dir(parent)?;
-                    }
-                }
-
-                std::fs::copy(entry.path(), &target)?;
+                fs::create_dir(&target)?;
             }
+            self.journal.record(Operation::Create {
+                to: target.clone(),
+                is_dir: !is_file,
+                contents: Vec::new(),
+                permissions: None,
+            });
         }
+        Ok(top)
+    }
 
-        self.to_registered_mut(&item, trash_path, trash_name);
+    //Make a new file from the template, replacing `{{name}}`, `{{stem}}`, `{{dir}}`,
+    //`{{date}}` and `{{author}}` in it. Templates that are not text are copied as they are.
+    pub fn make_from_template(&mut self, template: &Path, name: &str) -> std::io::Result<PathBuf> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(Error::other(format!("invalid name: \"{}\"", name)));
+        }
+        let to = self.current_dir.join(name);
+        let contents = fs::read(template)?;
+        let contents = match String::from_utf8(contents) {
+            Ok(text) => {
+                let variables = [
+                    ("name", name.to_string()),
+                    (
+                        "stem",
+                        Path::new(name)
+                            .file_stem()
+                            .map(|stem| stem.to_string_lossy().to_string())
+                            .unwrap_or_default(),
+                    ),
+                    (
+                        "dir",
+                        self.current_dir
+                            .file_name()
+                            .map(|dir| dir.to_string_lossy().to_string())
+                            .unwrap_or_default(),
+                    ),
+                    ("date", Local::now().format("%Y-%m-%d").to_string()),
+                    ("author", self.author.clone()),
+                ];
+                fill_variables(&text, &variables).into_bytes()
+            }
+            Err(e) => e.into_bytes(),
+        };
+        let permissions = fs::metadata(template)?.permissions();
 
-        //remove original
-        std::fs::remove_dir_all(&item.file_path)?;
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&to)?;
+        file.write_all(&contents)?;
+        fs::set_permissions(&to, permissions.clone())?;
 
-        Ok(())
+        self.journal.record(Operation::Create {
+            to: to.clone(),
+            is_dir: false,
+            contents,
+            permissions: Some(permissions),
+        });
+        self.journal.commit("template");
+        Ok(to)
+    }
+
+    //Files in the templates directory, sorted by name.
+    pub fn list_templates(&self) -> std::io::Result<Vec<PathBuf>> {
+        let mut templates: Vec<PathBuf> = fs::read_dir(&self.templates_dir)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect();
+        templates.sort();
+        Ok(templates)
     }
 
-    fn to_registered_mut(&mut self, item: &ItemInfo, file_path: PathBuf, file_name: String) {
+    //Turn permission bits (rwxrwxrwx) of the items on and off, keeping the other bits of each item.
+    //With recursive, items in directories are changed too, except symlinks.
+    pub fn chmod_items(
+        &mut self,
+        paths: &[PathBuf],
+        change: &ModeChange,
+    ) -> std::io::Result<usize> {
+        let result = chmod_paths(&mut self.journal, paths, change);
+        self.journal.commit("chmod");
+        result
+    }
+
+    //Move the item in the trash back to where it was deleted from.
+    //If something now exists at that path, the item is renamed just like when put.
+    pub fn restore_item(&mut self, item: &ItemInfo) -> std::io::Result<PathBuf> {
+        //not run in background, so a cancel left from the last operation is cleared here
+        self.progress.reset();
+        let result = self.restore_path(item);
+        self.journal.commit("restore");
+        result
+    }
+
+    fn restore_path(&mut self, item: &ItemInfo) -> std::io::Result<PathBuf> {
+        let trash_dir = trash::trash_root(&self.trash_dir, &item.file_path)
+            .unwrap_or_else(|| self.trash_dir.clone());
+        let info = trash::read_info(&trash_dir, &item.file_name)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "cannot read trash info."))?;
+        let parent = info
+            .original_path
+            .parent()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "cannot find original directory."))?;
+        fs::create_dir_all(parent)?;
+
+        let name_set = make_name_set(parent)?;
         let mut buf = item.clone();
-        buf.file_path = file_path;
-        buf.file_name = file_name;
-        buf.selected = false;
-        self.registered.push(buf);
+        buf.file_name = trash::original_name(&trash_dir, &item.file_name);
+        let rename = match item.file_type {
+            FileType::Directory => rename_dir(&buf, &name_set),
+            FileType::File | FileType::Symlink => rename_file(&buf, &name_set),
+        };
+        let to = parent.join(rename);
+
+        move_item(&item.file_path, &to, &self.progress)?;
+        self.journal.record(Operation::Restore {
+            from: item.file_path.clone(),
+            to: to.clone(),
+        });
+        trash::unregister(&trash_dir, &item.file_name)?;
+        Ok(to)
+    }
+
+    //Restore the items deleted last time, which remain registered.
+    pub fn restore_registered(&mut self) -> std::io::Result<usize> {
+        self.progress.reset();
+        let result = self.restore_all();
+        self.journal.commit("restore");
+        result
+    }
+
+    fn restore_all(&mut self) -> std::io::Result<usize> {
+        let mut count = 0;
+        for item in self.registered.clone().iter() {
+            if trash::trash_root(&self.trash_dir, &item.file_path).is_some()
+                && item.file_path.exists()
+            {
+                self.restore_path(item)?;
+                count += 1;
+            }
+        }
+        self.registered.retain(|item| item.file_path.exists());
+        Ok(count)
+    }
+
+    pub fn purge_trash(&self) -> std::io::Result<usize> {
+        trash::purge(&self.trash_dir, &self.trash_config)
     }
 
     pub fn yank_item(&mut self, index: usize, selected: bool) {
         self.registered.clear();
+        self.cut = false;
         if selected {
             for item in self.list.iter_mut().filter(|item| item.selected) {
                 self.registered.push(item.clone());
@@ -250,290 +616,136 @@ impl State {
         }
     }
 
-    pub fn put_items(&mut self) -> std::io::Result<()> {
-        //make HashSet<String> of file_name
-        let mut name_set = HashSet::new();
-        for item in self.list.iter() {
-            name_set.insert(item.file_name.clone());
+    //Yank items to be moved, not copied, when put.
+    pub fn cut_item(&mut self, index: usize, selected: bool) {
+        self.yank_item(index, selected);
+        self.cut = true;
+    }
+
+    //Name of the item when put: items in the trash get their original name back.
+    pub fn put_name(&self, item: &ItemInfo) -> OsString {
+        match trash::trash_root(&self.trash_dir, &item.file_path) {
+            Some(trash_dir) => trash::original_name(&trash_dir, &item.file_name),
+            None => item.file_name.clone(),
         }
+    }
 
-        for item in self.registered.clone().into_iter() {
-            match item.file_type {
-                FileType::Directory => {
-                    self.put_dir(&item, &mut name_set)?;
-                }
-                FileType::File | FileType::Symlink => {
-                    self.put_file(&item, &mut name_set)?;
+    //Decide how to put the item whose name already exists in the current directory.
+    //Overwrite merges directories, and falls back to rename if the file types differ.
+    pub fn resolve_conflict(
+        &self,
+        item: &ItemInfo,
+        policy: ConflictPolicy,
+        name_set: &HashSet<OsString>,
+    ) -> PutAction {
+        let name = self.put_name(item);
+        let is_dir = item.file_type == FileType::Directory;
+        let same_type = match fs::symlink_metadata(self.current_dir.join(&name)) {
+            Ok(metadata) => metadata.is_dir() == is_dir,
+            Err(_) => false,
+        };
+        match policy {
+            ConflictPolicy::Skip => PutAction::Skip,
+            ConflictPolicy::Overwrite if same_type && is_dir => PutAction::Merge(name),
+            ConflictPolicy::Overwrite if same_type => PutAction::Overwrite(name),
+            _ => {
+                let mut buf = item.clone();
+                buf.file_name = name;
+                if is_dir {
+                    PutAction::Rename(rename_dir(&buf, name_set))
+                } else {
+                    PutAction::Rename(rename_file(&buf, name_set))
                 }
             }
         }
-        Ok(())
     }
 
-    fn put_file(&mut self, item: &ItemInfo, name_set: &mut HashSet<String>) -> std::io::Result<()> {
-        if item.file_path.parent() == Some(&self.trash_dir) {
-            let mut item = item.clone();
-            let rename = item.file_name.chars().skip(11).collect();
-            item.file_name = rename;
-            let rename = rename_file(&item, name_set);
-            std::fs::copy(&item.file_path, &self.current_dir.join(&rename))?;
-            name_set.insert(rename);
-        } else {
-            let rename = rename_file(item, name_set);
-            std::fs::copy(&item.file_path, &self.current_dir.join(&rename))?;
-            name_set.insert(rename);
-        }
-        Ok(())
+    //Make links to registered items instead of copying them.
+    //Names are renamed in the same way as put, and registered items are kept.
+    pub fn link_items(&mut self, kind: LinkKind) -> std::io::Result<usize> {
+        let result = self.link_registered(kind);
+        self.journal.commit("link");
+        result
     }
 
-    fn put_dir(&mut self, buf: &ItemInfo, name_set: &mut HashSet<String>) -> std::io::Result<()> {
-        let mut base: usize = 0;
-        let mut target: PathBuf = PathBuf::new();
-        let original_path = &(buf).file_path;
-
-        let mut i = 0;
-        for entry in walkdir::WalkDir::new(&original_path).sort_by_key(|x| x.path().to_path_buf()) {
-            let entry = entry?;
-            if i == 0 {
-                base = entry.path().iter().count();
-
-                let parent = &original_path.parent().unwrap();
-                if parent == &self.trash_dir {
-                    let mut buf = buf.clone();
-                    let rename = buf.file_name.chars().skip(11).collect();
-                    buf.file_name = rename;
-
-                    let rename = rename_dir(&buf, name_set);
-                    target = self.current_dir.join(&rename);
-                    name_set.insert(rename);
-                } else {
-                    let rename = rename_dir(buf, name_set);
-                    target = self.current_dir.join(&rename);
-                    name_set.insert(rename);
-                }
-                std::fs::create_dir(&target)?;
-                i += 1;
-                continue;
-            } else {
-                let child: PathBuf = entry.path().iter().skip(base).collect();
-                let child = target.join(child);
-
-                if entry.file_type().is_dir() {
-                    std::fs::create_dir(child)?;
-                    continue;
-                } else if let Some(parent) = entry.path().parent() {
-                    if !parent.exists() {
-                        std::fs::create_dir(parent)?;
-                    }
-                }
+    fn link_registered(&mut self, kind: LinkKind) -> std::io::Result<usize> {
+        if kind == LinkKind::HardLink
+            && self
+                .registered
+                .iter()
+                .any(|item| item.file_type == FileType::Directory)
+        {
+            return Err(Error::other("cannot make hard link to directory."));
+        }
 
-                std::fs::copy(entry.path(), &child)?;
-            }
+        let mut name_set = make_name_set(&self.current_dir)?;
+        let mut count = 0;
+        for item in self.registered.iter() {
+            let mut item = item.clone();
+            item.file_name = self.put_name(&item);
+            let name = match item.file_type {
+                FileType::Directory => rename_dir(&item, &name_set),
+                _ => rename_file(&item, &name_set),
+            };
+            let target = self.current_dir.join(&name);
+            make_link(&item.file_path, &target, kind)?;
+            self.journal.record(Operation::Link {
+                from: item.file_path.clone(),
+                to: target,
+                kind,
+            });
+            name_set.insert(name);
+            count += 1;
         }
-        Ok(())
+        Ok(count)
     }
 
     pub fn print(&self, index: usize) {
         let item = &self.get_item(index).unwrap();
-        let chars: Vec<char> = item.file_name.chars().collect();
-        let name = if chars.len() > self.layout.name_max_len {
-            let mut result = chars
-                .iter()
-                .take(self.layout.name_max_len - 3)
-                .collect::<String>();
-            result.push_str("..");
-            result
+        let name_max_len = self.layout.name_max_len;
+        let escaped = escape_name(&item.file_name);
+        //names with escapes stand out, since they may try to look like other names
+        let color = if escaped.as_str() != item.file_name {
+            to_fg(&Colorname::LightRed)
         } else {
-            item.file_name.clone()
-        };
-        let time = format_time(&item.modified);
-        let selected = &item.selected;
-        let color = match item.file_type {
-            FileType::Directory => &self.colors.0,
-            FileType::File => &self.colors.1,
-            FileType::Symlink => &self.colors.2,
-        };
-        match color {
-            Colorname::AnsiValue(n) => {
-                print_item!(
-                    color::Fg(color::AnsiValue(*n)),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::Black => {
-                print_item!(
-                    color::Fg(color::Black),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::Blue => {
-                print_item!(
-                    color::Fg(color::Blue),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::Cyan => {
-                print_item!(
-                    color::Fg(color::Cyan),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::Green => {
-                print_item!(
-                    color::Fg(color::Green),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::LightBlack => {
-                print_item!(
-                    color::Fg(color::LightBlack),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::LightBlue => {
-                print_item!(
-                    color::Fg(color::LightBlue),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::LightCyan => {
-                print_item!(
-                    color::Fg(color::LightCyan),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::LightGreen => {
-                print_item!(
-                    color::Fg(color::LightGreen),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::LightMagenta => {
-                print_item!(
-                    color::Fg(color::LightMagenta),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::LightRed => {
-                print_item!(
-                    color::Fg(color::LightRed),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::LightWhite => {
-                print_item!(
-                    color::Fg(color::LightWhite),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::LightYellow => {
-                print_item!(
-                    color::Fg(color::LightYellow),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::Magenta => {
-                print_item!(
-                    color::Fg(color::Magenta),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::Red => {
-                print_item!(
-                    color::Fg(color::Red),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::Rgb(x, y, z) => {
-                print_item!(
-                    color::Fg(color::Rgb(*x, *y, *z)),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::White => {
-                print_item!(
-                    color::Fg(color::White),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
-            }
-            Colorname::Yellow => {
-                print_item!(
-                    color::Fg(color::Yellow),
-                    name,
-                    time,
-                    selected,
-                    self.layout.time_start_pos,
-                    self.layout.terminal_column
-                );
+            match item.file_type {
+                FileType::Directory => to_fg(&self.colors.0),
+                FileType::File => to_fg(&self.colors.1),
+                FileType::Symlink => to_fg(&self.colors.2),
             }
+        };
+        let name = pad_to_width(&shorten_name(&escaped, name_max_len), name_max_len);
+        let columns: String = self
+            .layout
+            .columns
+            .iter()
+            .map(|column| {
+                format!(
+                    " {}",
+                    format_column(
+                        item,
+                        column,
+                        &self.dir_sizes,
+                        &self.column_cache,
+                        &self.format
+                    )
+                )
+            })
+            .collect();
+
+        if item.selected {
+            print!(
+                "{}{}{}{}{}{}{}",
+                color,
+                style::Invert,
+                name,
+                style::Reset,
+                style::Invert,
+                columns,
+                style::Reset,
+            );
+        } else {
+            print!("{}{}{}{}", color, name, color::Fg(color::Reset), columns,);
         }
     }
 
@@ -581,33 +793,245 @@ impl State {
         }
     }
 
+    pub fn list_up_trash(&self, items: &[TrashItem], index: usize, skip: usize) {
+        clear_and_show(&trash::files_dir(&self.trash_dir));
+        let row = self.layout.terminal_row;
+        let column = self.layout.terminal_column;
+        //names in the trash are followed by the deletion time, size and original path
+        let name_max_len = self.layout.name_max_len.min(TRASH_NAME_LEN);
+        let time_pos = name_max_len as u16 + 4;
+        let time_width = time_width(&self.format);
+        let size_width = size_width(&self.format);
+        let size_pos = time_pos + time_width as u16 + 2;
+        let path_pos = size_pos + size_width as u16 + 2;
+
+        for (i, trash_item) in items
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .take((row - STARTING_POINT) as usize)
+        {
+            let y = (i - skip) as u16 + STARTING_POINT;
+            let name = trash_item.display_name();
+            let name = shorten_name(&name, name_max_len);
+            let color = match trash_item.item.file_type {
+                FileType::Directory => to_fg(&self.colors.0),
+                FileType::File => to_fg(&self.colors.1),
+                FileType::Symlink => to_fg(&self.colors.2),
+            };
+            print!(
+                "{}{}{}{}",
+                cursor::Goto(3, y),
+                color,
+                name,
+                color::Fg(color::Reset)
+            );
+
+            if column > size_pos + size_width as u16 {
+                let time = trash_item
+                    .deletion_date()
+                    .and_then(|date| Local.from_local_datetime(&date).single())
+                    .map(|date| format_date(date, &self.format))
+                    .unwrap_or_default();
+                print!(
+                    "{}{}{}{:>width$}",
+                    cursor::Goto(time_pos, y),
+                    time,
+                    cursor::Goto(size_pos, y),
+                    format_size(trash_item.size, &self.format),
+                    width = size_width
+                );
+            }
+
+            if column > path_pos + 1 {
+                if let Some(parent) = trash_item
+                    .info
+                    .as_ref()
+                    .and_then(|info| info.original_path.parent())
+                {
+                    let parent = truncate_to_width(
+                        &escape_name(parent.as_os_str()),
+                        (column - path_pos) as usize,
+                    );
+                    print!("{}{}", cursor::Goto(path_pos, y), parent);
+                }
+            }
+        }
+
+        print!("{}{}", cursor::Goto(1, row), clear::CurrentLine);
+        if let Some(trash_item) = items.get(index) {
+            let original = match &trash_item.info {
+                Some(info) => escape_name(info.original_path.as_os_str()),
+                None => "(no trash info)".to_string(),
+            };
+            print!("[{}/{}] {}", index + 1, items.len(), original);
+        }
+        print!(
+            "{}>{}",
+            cursor::Goto(1, (index - skip) as u16 + STARTING_POINT),
+            cursor::Left(1)
+        );
+    }
+
+    //Rows of the disk usage view: size, percentage of the directory, bar and name.
+    pub fn list_up_usage(
+        &self,
+        dir: &Path,
+        items: &[UsageItem],
+        total: u64,
+        index: usize,
+        skip: usize,
+    ) {
+        clear_and_show(dir);
+        let row = self.layout.terminal_row;
+        let column = self.layout.terminal_column as usize;
+
+        for (i, usage_item) in items
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .take((row - STARTING_POINT) as usize)
+        {
+            let y = (i - skip) as u16 + STARTING_POINT;
+            let ratio = if total == 0 {
+                0.0
+            } else {
+                usage_item.size as f64 / total as f64
+            };
+            let filled = ((ratio * USAGE_BAR_WIDTH as f64).round() as usize).min(USAGE_BAR_WIDTH);
+            let bar: String = (0..USAGE_BAR_WIDTH)
+                .map(|i| if i < filled { '#' } else { ' ' })
+                .collect();
+            let mut name = escape_name(&usage_item.item.file_name);
+            if usage_item.item.file_type == FileType::Directory {
+                name.push('/');
+            }
+            let line = format!(
+                "{:>width$} {:>5.1}% [{}] ",
+                format_size(usage_item.size, &self.format),
+                ratio * 100.0,
+                bar,
+                width = size_width(&self.format)
+            );
+            let name = shorten_name(&name, column.saturating_sub(3 + line.len()));
+            let line = truncate_to_width(&(line + &name), column.saturating_sub(3));
+            let color = match usage_item.item.file_type {
+                FileType::Directory => to_fg(&self.colors.0),
+                FileType::File => to_fg(&self.colors.1),
+                FileType::Symlink => to_fg(&self.colors.2),
+            };
+            print!(
+                "{}{}{}{}",
+                cursor::Goto(3, y),
+                color,
+                line,
+                color::Fg(color::Reset)
+            );
+        }
+
+        print!("{}{}", cursor::Goto(1, row), clear::CurrentLine);
+        print!(
+            "[{}/{}] total {}",
+            (index + 1).min(items.len()),
+            items.len(),
+            format_size(total, &self.format)
+        );
+        print!(
+            "{}>{}",
+            cursor::Goto(1, (index - skip) as u16 + STARTING_POINT),
+            cursor::Left(1)
+        );
+    }
+
     pub fn update_list(&mut self) {
+        for path in self.journal.take_touched() {
+            self.dir_sizes.invalidate(&path);
+        }
         self.list = push_items(&self.current_dir, &self.sort_by).unwrap();
+        self.column_cache.update(&self.list, &self.layout.columns);
+        if self.shows_dir_sizes() {
+            self.request_dir_sizes(false);
+        }
+        self.sort_by_dir_size();
+    }
+
+    //Sizes of directories are needed for the size column or sorting by size.
+    pub fn shows_dir_sizes(&self) -> bool {
+        matches!(self.sort_by, SortKey::Size)
+            || self
+                .layout
+                .columns
+                .iter()
+                .any(|column| matches!(column.kind, ColumnKind::Size))
+    }
+
+    pub fn request_dir_sizes(&self, force: bool) {
+        let dirs = self
+            .list
+            .iter()
+            .filter(|item| item.file_type == FileType::Directory)
+            .map(|item| item.file_path.clone())
+            .collect();
+        self.dir_sizes.request(dirs, force);
+    }
+
+    //Directories are sorted by the sizes computed so far. Those not computed yet go last.
+    pub fn sort_by_dir_size(&mut self) {
+        if let SortKey::Size = self.sort_by {
+            let dirs = self
+                .list
+                .iter()
+                .take_while(|item| item.file_type == FileType::Directory)
+                .count();
+            let dir_sizes = &self.dir_sizes;
+            self.list[..dirs]
+                .sort_by_cached_key(|item| std::cmp::Reverse(dir_sizes.get(&item.file_path)));
+        }
+    }
+
+    pub fn selected_items(&self) -> Vec<ItemInfo> {
+        self.list
+            .iter()
+            .filter(|item| item.selected)
+            .cloned()
+            .collect()
+    }
+
+    //Registered items and the journal are copied, so that they are kept if the worker panics.
+    pub fn file_ops(&self) -> FileOps {
+        FileOps {
+            current_dir: self.current_dir.clone(),
+            trash_dir: self.trash_dir.clone(),
+            copy_config: self.copy_config.clone(),
+            progress: self.progress.clone(),
+            dir_sizes: self.dir_sizes.clone(),
+            registered: self.registered.clone(),
+            cut: self.cut,
+            journal: self.journal.clone(),
+        }
+    }
+
+    pub fn finish_file_ops(&mut self, ops: FileOps) {
+        self.registered = ops.registered;
+        self.cut = ops.cut;
+        self.journal = ops.journal;
     }
 
     pub fn reset_selection(&mut self) {
-        for mut item in self.list.iter_mut() {
+        for item in self.list.iter_mut() {
             item.selected = false;
         }
     }
 
     pub fn select_from_top(&mut self, start_pos: usize) {
         for (i, item) in self.list.iter_mut().enumerate() {
-            if i <= start_pos {
-                item.selected = true;
-            } else {
-                item.selected = false;
-            }
+            item.selected = i <= start_pos;
         }
     }
 
     pub fn select_to_bottom(&mut self, start_pos: usize) {
         for (i, item) in self.list.iter_mut().enumerate() {
-            if i < start_pos {
-                item.selected = false;
-            } else {
-                item.selected = true;
-            }
+            item.selected = i >= start_pos;
         }
     }
 
@@ -625,8 +1049,8 @@ impl State {
                         "[{}/{}] {} {}",
                         nums.index + 1,
                         self.list.len(),
-                        ext.clone().into_string().unwrap_or_default(),
-                        to_proper_size(item.file_size)
+                        escape_name(ext),
+                        format_size(item.file_size, &self.format)
                     );
                 }
                 None => {
@@ -635,7 +1059,7 @@ impl State {
                         "[{}/{}] {}",
                         nums.index + 1,
                         self.list.len(),
-                        to_proper_size(item.file_size)
+                        format_size(item.file_size, &self.format)
                     );
                     debug!("no extensions arm finished.");
                 }
@@ -646,6 +1070,285 @@ impl State {
     }
 }
 
+//What file operations on a worker thread need from State.
+//They are taken out of State and put back when finished, so that the rest of State is not copied.
+#[derive(Debug)]
+pub struct FileOps {
+    pub current_dir: PathBuf,
+    pub trash_dir: PathBuf,
+    pub copy_config: CopyConfig,
+    pub progress: Arc<Progress>,
+    pub dir_sizes: DirSizes,
+    pub registered: Vec<ItemInfo>,
+    pub cut: bool,
+    pub journal: Journal,
+}
+
+impl FileOps {
+    //Undo and redo remove or move items, so registered ones may be gone.
+    pub fn undo(&mut self) -> std::io::Result<String> {
+        let result = self.journal.undo(&self.trash_dir, &self.progress);
+        self.registered.retain(|item| item.file_path.exists());
+        result
+    }
+
+    pub fn redo(&mut self) -> std::io::Result<String> {
+        let result = self
+            .journal
+            .redo(&self.trash_dir, &self.progress, &self.copy_config);
+        self.registered.retain(|item| item.file_path.exists());
+        result
+    }
+
+    fn move_to_trash(&mut self, item: ItemInfo) -> std::io::Result<()> {
+        let to = trash::move_to_trash(&self.trash_dir, &item.file_path, &self.progress)?;
+        let trash_name = to.file_name().unwrap().to_os_string();
+        self.journal.record(Operation::Trash {
+            from: item.file_path.clone(),
+            to: to.clone(),
+        });
+        self.to_registered_mut(&item, to, trash_name);
+        Ok(())
+    }
+
+    fn to_registered_mut(&mut self, item: &ItemInfo, file_path: PathBuf, file_name: OsString) {
+        let mut buf = item.clone();
+        buf.file_path = file_path;
+        buf.file_name = file_name;
+        buf.selected = false;
+        self.registered.push(buf);
+        self.cut = false;
+    }
+
+    pub fn remove_and_yank_file(&mut self, item: ItemInfo) -> std::io::Result<()> {
+        let from = &item.file_path;
+
+        if item.file_type == FileType::Symlink && !from.exists() {
+            let _ = Command::new("rm").arg(from).status();
+            Ok(())
+        } else {
+            self.move_to_trash(item)
+        }
+    }
+
+    pub fn remove_and_yank_dir(&mut self, item: ItemInfo) -> std::io::Result<()> {
+        self.move_to_trash(item)
+    }
+
+    pub fn remove_and_yank(&mut self, item: ItemInfo) -> std::io::Result<()> {
+        match item.file_type {
+            FileType::Directory => self.remove_and_yank_dir(item),
+            FileType::File | FileType::Symlink => self.remove_and_yank_file(item),
+        }
+    }
+
+    pub fn remove_and_yank_selected(&mut self, selected: Vec<ItemInfo>) -> std::io::Result<usize> {
+        self.registered.clear();
+        for item in selected.iter() {
+            let (bytes, files) = trash::count_trash(&self.trash_dir, &item.file_path);
+            self.progress.add_total(bytes, files);
+        }
+        let result = selected.into_iter().try_fold(0, |count, item| {
+            self.remove_and_yank(item).map(|_| count + 1)
+        });
+        self.journal.commit("delete");
+        result
+    }
+
+    //Put items as planned. Cut items are moved, with rename when on the same filesystem.
+    pub fn put_items(&mut self, plan: &[(ItemInfo, PutAction)]) -> std::io::Result<usize> {
+        let result = self.put_plan(plan);
+        self.journal.commit("put");
+        result
+    }
+
+    fn put_plan(&mut self, plan: &[(ItemInfo, PutAction)]) -> std::io::Result<usize> {
+        self.count_plan(plan);
+        let mut count = 0;
+        for (item, action) in plan.iter() {
+            let name = match action {
+                PutAction::Skip => continue,
+                PutAction::Rename(name) | PutAction::Overwrite(name) | PutAction::Merge(name) => {
+                    name
+                }
+            };
+            let target = self.current_dir.join(name);
+            if item.file_path == target {
+                //cut and put in the same directory
+                if self.cut {
+                    self.registered.retain(|x| x.file_path != item.file_path);
+                }
+                continue;
+            }
+
+            //the overwritten file goes to the trash, so that put can be undone
+            if let PutAction::Overwrite(_) = action {
+                let trashed = trash::move_to_trash(&self.trash_dir, &target, &self.progress)?;
+                self.journal.record(Operation::Trash {
+                    from: target.clone(),
+                    to: trashed,
+                });
+            }
+
+            let trash_dir = trash::trash_root(&self.trash_dir, &item.file_path);
+            //items cut from the trash lose their trash info, so they cannot go back
+            let record_move = trash_dir.is_none();
+            match action {
+                PutAction::Merge(_) => self.merge_dir(&item.file_path, &target, record_move)?,
+                _ => self.put_entry(&item.file_path, &target, record_move)?,
+            }
+
+            if self.cut {
+                if let Some(trash_dir) = &trash_dir {
+                    let _ = trash::unregister(trash_dir, &item.file_name);
+                }
+                self.registered.retain(|x| x.file_path != item.file_path);
+            }
+            count += 1;
+        }
+        if self.registered.is_empty() {
+            self.cut = false;
+        }
+        Ok(count)
+    }
+
+    //Totals of the whole plan are counted before the first item is put.
+    fn count_plan(&self, plan: &[(ItemInfo, PutAction)]) {
+        for (item, action) in plan.iter() {
+            let target = match action {
+                PutAction::Skip => continue,
+                PutAction::Rename(name) | PutAction::Overwrite(name) | PutAction::Merge(name) => {
+                    self.current_dir.join(name)
+                }
+            };
+            if item.file_path == target {
+                continue;
+            }
+            if let PutAction::Overwrite(_) = action {
+                let (bytes, files) = trash::count_trash(&self.trash_dir, &target);
+                self.progress.add_total(bytes, files);
+            }
+            let (bytes, files) = if self.cut {
+                count_move(&item.file_path, &self.current_dir)
+            } else {
+                count_size(&item.file_path)
+            };
+            self.progress.add_total(bytes, files);
+        }
+    }
+
+    fn put_entry(&mut self, from: &Path, to: &Path, record_move: bool) -> std::io::Result<()> {
+        if self.cut {
+            move_item(from, to, &self.progress)?;
+            if record_move {
+                self.journal.record(Operation::Move {
+                    from: from.to_path_buf(),
+                    to: to.to_path_buf(),
+                });
+            } else {
+                self.dir_sizes.invalidate(to);
+            }
+        } else {
+            copy_item(from, to, &self.progress, &self.copy_config)?;
+            self.journal.record(Operation::Copy {
+                from: from.to_path_buf(),
+                to: to.to_path_buf(),
+            });
+        }
+        Ok(())
+    }
+
+    //Merge the directory into the existing one entry by entry, resolving conflicts as Overwrite:
+    //directories are merged, other items of the same type replaced, and the rest renamed.
+    //Cut directories are removed only when they end up empty.
+    fn merge_dir(&mut self, from: &Path, to: &Path, record_move: bool) -> std::io::Result<()> {
+        let mut name_set = make_name_set(to)?;
+        for item in push_items(from, &SortKey::Name)? {
+            let mut target = to.join(&item.file_name);
+            let is_dir = item.file_type == FileType::Directory;
+            match fs::symlink_metadata(&target) {
+                Err(_) => {}
+                Ok(metadata) if metadata.is_dir() && is_dir => {
+                    self.merge_dir(&item.file_path, &target, record_move)?;
+                    continue;
+                }
+                Ok(metadata) if metadata.is_dir() == is_dir => {
+                    let trashed = trash::move_to_trash(&self.trash_dir, &target, &self.progress)?;
+                    self.journal.record(Operation::Trash {
+                        from: target.clone(),
+                        to: trashed,
+                    });
+                }
+                Ok(_) => {
+                    let name = if is_dir {
+                        rename_dir(&item, &name_set)
+                    } else {
+                        rename_file(&item, &name_set)
+                    };
+                    target = to.join(&name);
+                    name_set.insert(name);
+                }
+            }
+            self.put_entry(&item.file_path, &target, record_move)?;
+        }
+        if self.cut {
+            let permissions = fs::metadata(from)
+                .ok()
+                .map(|metadata| metadata.permissions());
+            match fs::remove_dir(from) {
+                Ok(_) if record_move => self.journal.record(Operation::RemoveDir {
+                    to: from.to_path_buf(),
+                    permissions,
+                }),
+                Ok(_) => {}
+                Err(e) => debug!("cannot remove {:?}: {}", from, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+//Contents go first if a directory cannot be read after the change, and last otherwise,
+//so that it is still readable while walking it.
+fn chmod_paths(
+    journal: &mut Journal,
+    paths: &[PathBuf],
+    change: &ModeChange,
+) -> std::io::Result<usize> {
+    let contents_first = change.clear & 0o500 != 0;
+    let max_depth = if change.recursive { usize::MAX } else { 0 };
+    let mut count = 0;
+    for path in paths.iter() {
+        //the target of a symlink is not changed, as with the items in directories
+        if fs::symlink_metadata(path)?.file_type().is_symlink() {
+            continue;
+        }
+        for entry in walkdir::WalkDir::new(path)
+            .max_depth(max_depth)
+            .contents_first(contents_first)
+        {
+            let entry = entry?;
+            if entry.file_type().is_symlink() {
+                continue;
+            }
+            let old_mode = entry.metadata()?.permissions().mode();
+            let in_dir_file = entry.depth() > 0 && !entry.file_type().is_dir();
+            let new_mode = change.apply(old_mode, in_dir_file);
+            if old_mode == new_mode {
+                continue;
+            }
+            fs::set_permissions(entry.path(), fs::Permissions::from_mode(new_mode))?;
+            journal.record(Operation::Chmod {
+                to: entry.path().to_path_buf(),
+                old_mode,
+                new_mode,
+            });
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
 fn make_item(dir: fs::DirEntry) -> ItemInfo {
     let path = dir.path();
     let metadata = &fs::symlink_metadata(&path);
@@ -675,10 +1378,7 @@ fn make_item(dir: fs::DirEntry) -> ItemInfo {
         Err(_) => FileType::File,
     };
 
-    let name = dir
-        .file_name()
-        .into_string()
-        .unwrap_or_else(|_| panic!("failed to get file name."));
+    let name = dir.file_name();
 
     let size = match metadata {
         Ok(metadata) => metadata.len(),
@@ -687,6 +1387,28 @@ fn make_item(dir: fs::DirEntry) -> ItemInfo {
 
     let ext = path.extension().map(|s| s.to_os_string());
 
+    let changed = match metadata {
+        Ok(metadata) => Local
+            .timestamp_opt(metadata.ctime(), 0)
+            .single()
+            .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, false)),
+        Err(_) => None,
+    };
+
+    let (permissions, uid, gid) = match metadata {
+        Ok(metadata) => (
+            metadata.permissions().mode(),
+            metadata.uid(),
+            metadata.gid(),
+        ),
+        Err(_) => (0, 0, 0),
+    };
+
+    let link_target = match filetype {
+        FileType::Symlink => fs::read_link(&path).ok(),
+        _ => None,
+    };
+
     ItemInfo {
         file_type: filetype,
         file_name: name,
@@ -694,6 +1416,11 @@ fn make_item(dir: fs::DirEntry) -> ItemInfo {
         file_size: size,
         file_ext: ext,
         modified: time,
+        changed,
+        permissions,
+        uid,
+        gid,
+        link_target,
         selected: false,
     }
 }
@@ -714,16 +1441,156 @@ pub fn push_items(p: &Path, key: &SortKey) -> Result<Vec<ItemInfo>, Error> {
 
     match key {
         SortKey::Name => {
-            dir_v.sort_by(|a, b| natord::compare(&a.file_name, &b.file_name));
-            file_v.sort_by(|a, b| natord::compare(&a.file_name, &b.file_name));
+            dir_v.sort_by(|a, b| {
+                natord::compare(
+                    &a.file_name.to_string_lossy(),
+                    &b.file_name.to_string_lossy(),
+                )
+            });
+            file_v.sort_by(|a, b| {
+                natord::compare(
+                    &a.file_name.to_string_lossy(),
+                    &b.file_name.to_string_lossy(),
+                )
+            });
         }
         SortKey::Time => {
             dir_v.sort_by(|a, b| b.modified.partial_cmp(&a.modified).unwrap());
             file_v.sort_by(|a, b| b.modified.partial_cmp(&a.modified).unwrap());
         }
+        //directories are sorted by their recursive sizes later
+        SortKey::Size => {
+            dir_v.sort_by(|a, b| {
+                natord::compare(
+                    &a.file_name.to_string_lossy(),
+                    &b.file_name.to_string_lossy(),
+                )
+            });
+            file_v.sort_by_key(|item| std::cmp::Reverse(item.file_size));
+        }
     }
 
     result.append(&mut dir_v);
     result.append(&mut file_v);
     Ok(result)
 }
+
+//Text of the column for the item, padded or truncated to its width.
+fn format_column(
+    item: &ItemInfo,
+    column: &Column,
+    dir_sizes: &DirSizes,
+    cache: &ColumnCache,
+    format: &FormatConfig,
+) -> String {
+    let text = match column.kind {
+        ColumnKind::Size => match item.file_type {
+            FileType::Directory => match dir_sizes.get(&item.file_path) {
+                Some(size) => format_size(size, format),
+                None => "-".to_string(),
+            },
+            _ => format_size(item.file_size, format),
+        },
+        ColumnKind::Permissions => format_permissions(item.permissions),
+        ColumnKind::Owner => match cache.users.get(&item.uid) {
+            Some(name) => name.clone(),
+            None => item.uid.to_string(),
+        },
+        ColumnKind::Group => match cache.groups.get(&item.gid) {
+            Some(name) => name.clone(),
+            None => item.gid.to_string(),
+        },
+        ColumnKind::Modified => format_time(&item.modified, format),
+        ColumnKind::Changed => format_time(&item.changed, format),
+        ColumnKind::Items => match item.file_type {
+            FileType::Directory => match cache.items.get(&item.file_path) {
+                Some(count) => count.to_string(),
+                None => "?".to_string(),
+            },
+            _ => "".to_string(),
+        },
+        ColumnKind::LinkTarget => match &item.link_target {
+            Some(target) => format!("-> {}", escape_name(target.as_os_str())),
+            None => "".to_string(),
+        },
+    };
+
+    let width = column.width(format) as usize;
+    let text = truncate_to_width(&text, width);
+    let padding = " ".repeat(width.saturating_sub(text.width()));
+    match column.align() {
+        Align::Left => text + &padding,
+        Align::Right => padding + &text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testdir::TestDir;
+    use super::*;
+
+    fn mode_of(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    fn set_mode(path: &Path, mode: u32) {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[test]
+    fn test_chmod_recursive_keeps_other_bits() {
+        let temp = TestDir::new();
+        let dir = temp.path().join("dir");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("private"), "").unwrap();
+        fs::write(dir.join("sub/script"), "").unwrap();
+        set_mode(&dir, 0o755);
+        set_mode(&dir.join("sub"), 0o700);
+        set_mode(&dir.join("private"), 0o600);
+        set_mode(&dir.join("sub/script"), 0o744);
+
+        //turn on group r and x, and turn off others r
+        let change = ModeChange {
+            set: 0o050,
+            clear: 0o004,
+            recursive: true,
+            file_x: false,
+        };
+        let mut journal = Journal::default();
+        let count = chmod_paths(&mut journal, std::slice::from_ref(&dir), &change).unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(mode_of(&dir), 0o751);
+        assert_eq!(mode_of(&dir.join("sub")), 0o750);
+        assert_eq!(mode_of(&dir.join("private")), 0o640);
+        assert_eq!(mode_of(&dir.join("sub/script")), 0o740);
+
+        //files get x bits only when asked
+        let change = ModeChange {
+            set: 0o001,
+            file_x: true,
+            ..change
+        };
+        chmod_paths(&mut journal, std::slice::from_ref(&dir), &change).unwrap();
+        assert_eq!(mode_of(&dir.join("private")), 0o641);
+        assert_eq!(mode_of(&dir.join("sub/script")), 0o741);
+    }
+
+    #[test]
+    fn test_chmod_file_gets_x_when_chosen() {
+        let temp = TestDir::new();
+        let file = temp.path().join("file");
+        fs::write(&file, "").unwrap();
+        set_mode(&file, 0o644);
+        let change = ModeChange {
+            set: 0o100,
+            ..Default::default()
+        };
+        chmod_paths(
+            &mut Journal::default(),
+            std::slice::from_ref(&file),
+            &change,
+        )
+        .unwrap();
+        assert_eq!(mode_of(&file), 0o744);
+    }
+}
@@ -0,0 +1,787 @@
+use super::config::{SortKey, TrashConfig};
+use super::functions::{count_move, count_size, escape_name, move_item};
+use super::progress::Progress;
+use super::state::{push_items, FileType, ItemInfo};
+use chrono::prelude::*;
+use log::warn;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::Write;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+pub const TRASH: &str = "Trash";
+pub const TRASH_FILES: &str = "files";
+pub const TRASH_INFO: &str = "info";
+pub const TRASHINFO_EXT: &str = "trashinfo";
+const SHARED_TRASH: &str = ".Trash";
+pub const OLD_TRASH: &str = "trash";
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+const SKIPPED_FS: [&str; 14] = [
+    "autofs",
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smbfs",
+    "smb3",
+    "ncpfs",
+    "ceph",
+    "glusterfs",
+    "fuse.glusterfs",
+    "fuse.sshfs",
+    "9p",
+    "afs",
+    "davfs",
+];
+
+//The trash follows the freedesktop.org Trash specification:
+//each trashed item lives in `files/`, and `info/<name>.trashinfo` keeps its original path and deletion date.
+#[derive(Debug, Clone)]
+pub struct TrashInfo {
+    pub original_path: PathBuf,
+    pub deletion_date: Option<NaiveDateTime>,
+}
+
+//Item shown in the trash view.
+#[derive(Debug, Clone)]
+pub struct TrashItem {
+    pub trash_dir: PathBuf,
+    pub item: ItemInfo,
+    pub info: Option<TrashInfo>,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrashSortKey {
+    Name,
+    Time,
+    Size,
+}
+
+impl TrashSortKey {
+    pub fn next(self) -> Self {
+        match self {
+            TrashSortKey::Name => TrashSortKey::Time,
+            TrashSortKey::Time => TrashSortKey::Size,
+            TrashSortKey::Size => TrashSortKey::Name,
+        }
+    }
+}
+
+pub fn make_trash(trash_dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(trash_dir.join(TRASH_FILES))?;
+    fs::create_dir_all(trash_dir.join(TRASH_INFO))?;
+    Ok(())
+}
+
+pub fn files_dir(trash_dir: &Path) -> PathBuf {
+    trash_dir.join(TRASH_FILES)
+}
+
+//`files/` of the home trash or of a trash directory at the top of the mount it is in.
+//Only paths are compared, so that no filesystem is touched.
+pub fn is_files_dir(home_trash: &Path, dir: &Path) -> bool {
+    dir.file_name() == Some(OsStr::new(TRASH_FILES))
+        && dir.parent().is_some_and(|parent| {
+            parent == home_trash
+                || mount_of(parent)
+                    .is_some_and(|mount| trash_candidates(&mount.dir).iter().any(|x| x == parent))
+        })
+}
+
+//Trash directory which the item lives in, if any.
+pub fn trash_root(home_trash: &Path, path: &Path) -> Option<PathBuf> {
+    path.parent()
+        .filter(|parent| is_files_dir(home_trash, parent))
+        .and_then(|parent| parent.parent())
+        .map(|root| root.to_path_buf())
+}
+
+//Trash directory for the item to be deleted: the home trash if the item is on the same filesystem,
+//otherwise `$topdir/.Trash/$uid` or `$topdir/.Trash-$uid` at the top of its mount point.
+pub fn trash_dir_for(home_trash: &Path, path: &Path) -> std::io::Result<PathBuf> {
+    let dir = path.parent().unwrap_or(path);
+    let dev = fs::metadata(dir)?.dev();
+    if dev == fs::metadata(home_trash)?.dev() {
+        return Ok(home_trash.to_path_buf());
+    }
+
+    let topdir = find_topdir(dir, dev);
+    let uid = users::get_current_uid();
+
+    //$topdir/.Trash must be a real directory with the sticky bit
+    let shared = topdir.join(SHARED_TRASH);
+    if let Ok(metadata) = fs::symlink_metadata(&shared) {
+        if metadata.is_dir() && metadata.permissions().mode() & 0o1000 != 0 {
+            let trash_dir = shared.join(uid.to_string());
+            if make_private_trash(&trash_dir).is_ok() {
+                return Ok(trash_dir);
+            }
+        }
+    }
+
+    let trash_dir = topdir.join(format!("{}-{}", SHARED_TRASH, uid));
+    match make_private_trash(&trash_dir) {
+        Ok(_) => Ok(trash_dir),
+        //e.g. read-only topdir: fall back to the home trash
+        Err(_) => Ok(home_trash.to_path_buf()),
+    }
+}
+
+//Home trash and trash directories of the mounted filesystems.
+//Network filesystems and autofs are skipped, as looking into them may hang or mount them.
+pub fn all_trash_dirs(home_trash: &Path) -> Vec<PathBuf> {
+    let mut result = vec![home_trash.to_path_buf()];
+    for mount in mounts().iter().filter(|mount| !mount.skipped) {
+        for trash_dir in trash_candidates(&mount.dir) {
+            if trash_dir != home_trash
+                && !result.contains(&trash_dir)
+                && files_dir(&trash_dir).is_dir()
+            {
+                result.push(trash_dir);
+            }
+        }
+    }
+    result
+}
+
+//`$topdir/.Trash/$uid` and `$topdir/.Trash-$uid`.
+fn trash_candidates(topdir: &Path) -> [PathBuf; 2] {
+    let uid = users::get_current_uid();
+    [
+        topdir.join(SHARED_TRASH).join(uid.to_string()),
+        topdir.join(format!("{}-{}", SHARED_TRASH, uid)),
+    ]
+}
+
+#[derive(Debug)]
+struct Mount {
+    dir: PathBuf,
+    skipped: bool,
+}
+
+//Mount points in /proc/mounts, read once per session.
+fn mounts() -> &'static [Mount] {
+    static MOUNTS: OnceLock<Vec<Mount>> = OnceLock::new();
+    MOUNTS.get_or_init(|| parse_mounts(&fs::read("/proc/mounts").unwrap_or_default()))
+}
+
+fn parse_mounts(mounts: &[u8]) -> Vec<Mount> {
+    mounts
+        .split(|byte| *byte == b'\n')
+        .filter_map(|line| {
+            let mut fields = line.split(|byte| *byte == b' ');
+            let dir = fields.nth(1)?;
+            let fs_type = fields.next()?;
+            Some(Mount {
+                dir: PathBuf::from(unescape_mount(dir)),
+                skipped: SKIPPED_FS.iter().any(|x| x.as_bytes() == fs_type),
+            })
+        })
+        .collect()
+}
+
+//Mount which the path is in, found by the path alone.
+fn mount_of(path: &Path) -> Option<&'static Mount> {
+    mounts()
+        .iter()
+        .filter(|mount| path.starts_with(&mount.dir))
+        .max_by_key(|mount| mount.dir.as_os_str().len())
+}
+
+fn make_private_trash(trash_dir: &Path) -> std::io::Result<()> {
+    if !trash_dir.exists() {
+        fs::DirBuilder::new().mode(0o700).create(trash_dir)?;
+    }
+    make_trash(trash_dir)
+}
+
+fn find_topdir(dir: &Path, dev: u64) -> PathBuf {
+    let mut topdir = dir.to_path_buf();
+    while let Some(parent) = topdir.parent() {
+        match fs::metadata(parent) {
+            Ok(metadata) if metadata.dev() == dev => topdir = parent.to_path_buf(),
+            _ => break,
+        }
+    }
+    topdir
+}
+
+//Directory which relative paths in .trashinfo start from.
+//Only trash directories under $topdir use relative paths.
+fn base_dir(trash_dir: &Path) -> Option<PathBuf> {
+    let name = trash_dir.file_name()?.to_string_lossy();
+    if name.starts_with(&format!("{}-", SHARED_TRASH)) {
+        return trash_dir.parent().map(|p| p.to_path_buf());
+    }
+    let parent = trash_dir.parent()?;
+    if parent.file_name() == Some(OsStr::new(SHARED_TRASH)) {
+        return parent.parent().map(|p| p.to_path_buf());
+    }
+    None
+}
+
+//Mount points in /proc/mounts escape spaces and such as octal (e.g. `\040`).
+fn unescape_mount(s: &[u8]) -> OsString {
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < s.len() {
+        if s[i] == b'\\' && i + 3 < s.len() {
+            let oct = std::str::from_utf8(&s[i + 1..i + 4]).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(oct, 8) {
+                result.push(byte);
+                i += 4;
+                continue;
+            }
+        }
+        result.push(s[i]);
+        i += 1;
+    }
+    OsString::from_vec(result)
+}
+
+pub fn info_path(trash_dir: &Path, trash_name: &OsStr) -> PathBuf {
+    let mut info_name = trash_name.to_os_string();
+    info_name.push(".");
+    info_name.push(TRASHINFO_EXT);
+    trash_dir.join(TRASH_INFO).join(info_name)
+}
+
+//Reserve a unique name in the trash by creating its .trashinfo file.
+//Returns the name to be used in `files/`.
+pub fn register(trash_dir: &Path, original_path: &Path) -> std::io::Result<OsString> {
+    register_at(trash_dir, original_path, Local::now().naive_local())
+}
+
+fn register_at(
+    trash_dir: &Path,
+    original_path: &Path,
+    deletion_date: NaiveDateTime,
+) -> std::io::Result<OsString> {
+    let name = original_path
+        .file_name()
+        .unwrap_or_else(|| OsStr::new("unnamed"));
+    let info_original_path = match base_dir(trash_dir) {
+        Some(base) => original_path
+            .strip_prefix(&base)
+            .unwrap_or(original_path)
+            .to_path_buf(),
+        None => original_path.to_path_buf(),
+    };
+
+    let mut i = 1;
+    loop {
+        let trash_name = numbered_name(name, i);
+        //symlink_metadata, so that a dangling symlink in the trash is not overwritten
+        if fs::symlink_metadata(files_dir(trash_dir).join(&trash_name)).is_err() {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(info_path(trash_dir, &trash_name))
+            {
+                Ok(mut file) => {
+                    let result = write!(
+                        file,
+                        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+                        encode_path(&info_original_path),
+                        deletion_date.format(DATE_FORMAT)
+                    );
+                    if let Err(e) = result {
+                        let _ = unregister(trash_dir, &trash_name);
+                        return Err(e);
+                    }
+                    return Ok(trash_name);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+        }
+        i += 1;
+    }
+}
+
+//Remove the .trashinfo file when the item could not be moved to the trash,
+//or when it leaves the trash.
+pub fn unregister(trash_dir: &Path, trash_name: &OsStr) -> std::io::Result<()> {
+    fs::remove_file(info_path(trash_dir, trash_name))
+}
+
+//Register the item and rename it into the trash, which copies and removes it only across filesystems.
+//Returns the path of the item in the trash.
+pub fn move_to_trash(
+    home_trash: &Path,
+    from: &Path,
+    progress: &Progress,
+) -> std::io::Result<PathBuf> {
+    let trash_dir = trash_dir_for(home_trash, from)?;
+    let trash_name = register(&trash_dir, from)?;
+    let to = files_dir(&trash_dir).join(&trash_name);
+
+    if let Err(e) = move_item(from, &to, progress) {
+        //the item stays registered only if it was copied whole but could not be removed after that
+        if fs::symlink_metadata(&to).is_err() {
+            let _ = unregister(&trash_dir, &trash_name);
+        }
+        return Err(e);
+    }
+    Ok(to)
+}
+
+//Older versions kept deleted items in `$XDG_CONFIG_HOME/felix/trash`, named `<timestamp>_<name>`.
+//They are moved to the home trash as if deleted from the home directory, which is where they are restored,
+//and the old directory is removed once empty.
+pub fn migrate_old_trash(
+    old_trash: &Path,
+    home_trash: &Path,
+    home: &Path,
+) -> std::io::Result<usize> {
+    if !old_trash.is_dir() {
+        return Ok(0);
+    }
+    let progress = Progress::new();
+    let mut count = 0;
+    for entry in fs::read_dir(old_trash)? {
+        let entry = entry?;
+        let (name, deletion_date) = split_old_name(&entry.file_name());
+        let trash_name = register_at(home_trash, &home.join(name), deletion_date)?;
+        let to = files_dir(home_trash).join(&trash_name);
+        if let Err(e) = move_item(&entry.path(), &to, &progress) {
+            if fs::symlink_metadata(&to).is_err() {
+                let _ = unregister(home_trash, &trash_name);
+            }
+            return Err(e);
+        }
+        count += 1;
+    }
+    fs::remove_dir(old_trash)?;
+    Ok(count)
+}
+
+//Name and deletion date of an item in the old trash.
+//Items not named by older versions are dated now.
+fn split_old_name(name: &OsStr) -> (OsString, NaiveDateTime) {
+    let bytes = name.as_bytes();
+    if let Some(pos) = bytes.iter().position(|byte| *byte == b'_') {
+        let date = std::str::from_utf8(&bytes[..pos])
+            .ok()
+            .and_then(|timestamp| timestamp.parse::<i64>().ok())
+            .and_then(|timestamp| Local.timestamp_opt(timestamp, 0).single());
+        if let Some(date) = date {
+            if pos + 1 < bytes.len() {
+                return (
+                    OsStr::from_bytes(&bytes[pos + 1..]).to_os_string(),
+                    date.naive_local(),
+                );
+            }
+        }
+    }
+    (name.to_os_string(), Local::now().naive_local())
+}
+
+//Bytes and files copied to move the item to the trash, as move_to_trash does.
+pub fn count_trash(home_trash: &Path, path: &Path) -> (u64, u64) {
+    match trash_dir_for(home_trash, path) {
+        Ok(trash_dir) => count_move(path, &trash_dir),
+        Err(_) => (0, 0),
+    }
+}
+
+pub fn read_info(trash_dir: &Path, trash_name: &OsStr) -> Option<TrashInfo> {
+    let content = fs::read_to_string(info_path(trash_dir, trash_name)).ok()?;
+    let mut original_path = None;
+    let mut deletion_date = None;
+    for line in content.lines() {
+        if let Some(path) = line.strip_prefix("Path=") {
+            let path = decode_path(path);
+            original_path = if path.is_absolute() {
+                Some(path)
+            } else {
+                base_dir(trash_dir)
+                    .or_else(|| trash_dir.parent().map(|p| p.to_path_buf()))
+                    .map(|base| base.join(path))
+            };
+        } else if let Some(date) = line.strip_prefix("DeletionDate=") {
+            deletion_date = NaiveDateTime::parse_from_str(date, DATE_FORMAT).ok();
+        }
+    }
+    Some(TrashInfo {
+        original_path: original_path?,
+        deletion_date,
+    })
+}
+
+//Name of the item before it was trashed, falling back to the name in the trash.
+pub fn original_name(trash_dir: &Path, trash_name: &OsStr) -> OsString {
+    read_info(trash_dir, trash_name)
+        .and_then(|info| {
+            info.original_path
+                .file_name()
+                .map(|name| name.to_os_string())
+        })
+        .unwrap_or_else(|| trash_name.to_os_string())
+}
+
+//Items in the home trash and the trash directories of the mounted filesystems.
+//Sizes of directories are computed here once, so sort the returned items to change the order.
+pub fn list_items(home_trash: &Path, key: TrashSortKey) -> std::io::Result<Vec<TrashItem>> {
+    let mut result = Vec::new();
+    for trash_dir in all_trash_dirs(home_trash) {
+        for item in push_items(&files_dir(&trash_dir), &SortKey::Name)? {
+            let info = read_info(&trash_dir, &item.file_name);
+            let size = match item.file_type {
+                FileType::Directory => count_size(&item.file_path).0,
+                FileType::File | FileType::Symlink => item.file_size,
+            };
+            result.push(TrashItem {
+                trash_dir: trash_dir.clone(),
+                item,
+                info,
+                size,
+            });
+        }
+    }
+    sort_items(&mut result, key);
+    Ok(result)
+}
+
+pub fn sort_items(items: &mut [TrashItem], key: TrashSortKey) {
+    match key {
+        TrashSortKey::Name => {
+            items.sort_by(|a, b| natord::compare(&a.display_name(), &b.display_name()))
+        }
+        TrashSortKey::Time => items.sort_by_key(|item| std::cmp::Reverse(item.deletion_date())),
+        TrashSortKey::Size => items.sort_by_key(|item| std::cmp::Reverse(item.size)),
+    }
+}
+
+impl TrashItem {
+    pub fn display_name(&self) -> String {
+        let name = self
+            .info
+            .as_ref()
+            .and_then(|info| info.original_path.file_name())
+            .unwrap_or(&self.item.file_name);
+        escape_name(name)
+    }
+
+    pub fn deletion_date(&self) -> Option<NaiveDateTime> {
+        self.info.as_ref().and_then(|info| info.deletion_date)
+    }
+}
+
+//Delete the item in the trash permanently.
+pub fn delete(trash_item: &TrashItem) -> std::io::Result<()> {
+    let item = &trash_item.item;
+    match item.file_type {
+        FileType::Directory => fs::remove_dir_all(&item.file_path)?,
+        FileType::File | FileType::Symlink => fs::remove_file(&item.file_path)?,
+    }
+    let info = info_path(&trash_item.trash_dir, &item.file_name);
+    if info.exists() {
+        fs::remove_file(info)?;
+    }
+    Ok(())
+}
+
+//Delete items older than max_days, then the oldest items until the trash fits in max_size (MB).
+pub fn purge(home_trash: &Path, config: &TrashConfig) -> std::io::Result<usize> {
+    if config.max_days.is_none() && config.max_size.is_none() {
+        return Ok(0);
+    }
+    //newest first
+    let items = list_items(home_trash, TrashSortKey::Time)?;
+    Ok(purge_items(
+        items,
+        config,
+        Local::now().naive_local(),
+        delete,
+    ))
+}
+
+//Items are sorted newest first. Items which cannot be deleted are skipped.
+fn purge_items<F>(
+    mut items: Vec<TrashItem>,
+    config: &TrashConfig,
+    now: NaiveDateTime,
+    mut delete: F,
+) -> usize
+where
+    F: FnMut(&TrashItem) -> std::io::Result<()>,
+{
+    let mut count = 0;
+    let mut delete = |item: &TrashItem| match delete(item) {
+        Ok(_) => {
+            count += 1;
+            true
+        }
+        Err(e) => {
+            warn!(
+                "cannot delete {:?} from the trash: {}",
+                item.item.file_path, e
+            );
+            false
+        }
+    };
+
+    if let Some(limit) = config
+        .max_days
+        .and_then(|max_days| expiry_limit(now, max_days))
+    {
+        items.retain(|item| match item.deletion_date() {
+            Some(date) if date < limit => !delete(item),
+            _ => true,
+        });
+    }
+
+    if let Some(max_size) = config.max_size {
+        let max_size = max_size.saturating_mul(1_000_000);
+        let mut total: u64 = items.iter().map(|item| item.size).sum();
+        //oldest first, and the next oldest is deleted instead of one which cannot be deleted
+        while total > max_size {
+            match items.pop() {
+                Some(item) => {
+                    if delete(&item) {
+                        total -= item.size;
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    count
+}
+
+//Items deleted before this time are expired.
+//None if max_days is too large to go back from now, so that nothing expires.
+fn expiry_limit(now: NaiveDateTime, max_days: u64) -> Option<NaiveDateTime> {
+    let secs = i64::try_from(max_days).ok()?.checked_mul(24 * 60 * 60)?;
+    //chrono::Duration holds milliseconds in i64, and panics beyond that
+    if secs > i64::MAX / 1000 {
+        return None;
+    }
+    now.checked_sub_signed(chrono::Duration::seconds(secs))
+}
+
+pub fn empty(home_trash: &Path) -> std::io::Result<()> {
+    for trash_dir in all_trash_dirs(home_trash) {
+        empty_dir(&trash_dir)?;
+    }
+    Ok(())
+}
+
+fn empty_dir(trash_dir: &Path) -> std::io::Result<()> {
+    fs::remove_dir_all(trash_dir.join(TRASH_FILES))?;
+    fs::remove_dir_all(trash_dir.join(TRASH_INFO))?;
+    let sizes = trash_dir.join("directorysizes");
+    if sizes.exists() {
+        fs::remove_file(sizes)?;
+    }
+    make_trash(trash_dir)
+}
+
+fn numbered_name(name: &OsStr, i: usize) -> OsString {
+    let mut result = name.to_os_string();
+    if i == 1 {
+        return result;
+    }
+    let path = Path::new(name);
+    if let (Some(stem), Some(ext)) = (path.file_stem(), path.extension()) {
+        result = stem.to_os_string();
+        result.push(format!(".{}.", i));
+        result.push(ext);
+    } else {
+        result.push(format!(".{}", i));
+    }
+    result
+}
+
+fn encode_path(path: &Path) -> String {
+    let mut result = String::new();
+    for byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                result.push(*byte as char)
+            }
+            _ => result.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    result
+}
+
+fn decode_path(path: &str) -> PathBuf {
+    let bytes = path.as_bytes();
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                result.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        result.push(bytes[i]);
+        i += 1;
+    }
+    PathBuf::from(OsStr::from_bytes(&result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testdir::TestDir;
+    use super::*;
+
+    #[test]
+    fn test_parse_mounts() {
+        let mounts = parse_mounts(
+            b"/dev/sda1 / ext4 rw 0 0\n\
+              /dev/sdb1 /mnt/my\\040disk\\377 ext4 rw 0 0\n\
+              server:/home /net/home nfs4 rw 0 0\n\
+              auto.misc /misc autofs rw 0 0\n",
+        );
+        assert_eq!(mounts.len(), 4);
+        assert_eq!(
+            mounts[1].dir.as_os_str().as_bytes(),
+            b"/mnt/my disk\xff".as_slice()
+        );
+        assert!(!mounts[1].skipped);
+        assert!(mounts[2].skipped);
+        assert!(mounts[3].skipped);
+    }
+
+    fn trash_item(name: &str, days_ago: i64, size: u64, now: NaiveDateTime) -> TrashItem {
+        TrashItem {
+            trash_dir: PathBuf::from("/trash"),
+            item: ItemInfo {
+                file_type: FileType::File,
+                file_name: OsString::from(name),
+                file_path: PathBuf::from("/trash/files").join(name),
+                file_size: size,
+                file_ext: None,
+                modified: None,
+                changed: None,
+                permissions: 0o644,
+                uid: 0,
+                gid: 0,
+                link_target: None,
+                selected: false,
+            },
+            info: Some(TrashInfo {
+                original_path: PathBuf::from("/home").join(name),
+                deletion_date: Some(now - chrono::Duration::days(days_ago)),
+            }),
+            size,
+        }
+    }
+
+    //Names of the deleted items of 400KB each, in the order deleted.
+    fn purged(config: &TrashConfig, failing: &str) -> Vec<String> {
+        let now = NaiveDate::from_ymd(2022, 1, 31).and_hms(12, 0, 0);
+        //newest first
+        let items = vec![
+            trash_item("new", 1, 400_000, now),
+            trash_item("middle", 10, 400_000, now),
+            trash_item("old", 20, 400_000, now),
+            trash_item("oldest", 40, 400_000, now),
+        ];
+        let mut deleted = Vec::new();
+        let count = purge_items(items, config, now, |item| {
+            let name = item.item.file_name.to_string_lossy().to_string();
+            if name == failing {
+                return Err(std::io::Error::other("busy"));
+            }
+            deleted.push(name);
+            Ok(())
+        });
+        assert_eq!(count, deleted.len());
+        deleted
+    }
+
+    #[test]
+    fn test_purge_by_age() {
+        let config = TrashConfig {
+            max_days: Some(15),
+            max_size: None,
+        };
+        assert_eq!(purged(&config, ""), vec!["old", "oldest"]);
+        assert_eq!(purged(&config, "old"), vec!["oldest"]);
+    }
+
+    #[test]
+    fn test_purge_by_size_oldest_first() {
+        let config = TrashConfig {
+            max_days: None,
+            max_size: Some(1),
+        };
+        assert_eq!(purged(&config, ""), vec!["oldest", "old"]);
+        //the next oldest is deleted instead of the one which cannot be deleted
+        assert_eq!(purged(&config, "oldest"), vec!["old", "middle"]);
+
+        let config = TrashConfig {
+            max_days: Some(15),
+            max_size: Some(0),
+        };
+        assert_eq!(purged(&config, ""), vec!["old", "oldest", "middle", "new"]);
+    }
+
+    #[test]
+    fn test_purge_with_huge_max_days() {
+        let config = TrashConfig {
+            max_days: Some(u64::MAX),
+            max_size: None,
+        };
+        assert!(purged(&config, "").is_empty());
+        let config = TrashConfig {
+            max_days: Some(i64::MAX as u64 / 86_400),
+            max_size: None,
+        };
+        assert!(purged(&config, "").is_empty());
+    }
+
+    #[test]
+    fn test_encode_path() {
+        let path = Path::new(OsStr::from_bytes(b"/home/user/a b%c/\xe6\x97\xa5\xff.txt"));
+        let encoded = encode_path(path);
+        assert_eq!(encoded, "/home/user/a%20b%25c/%E6%97%A5%FF.txt");
+        assert_eq!(decode_path(&encoded), path);
+        //broken escapes are kept as they are
+        assert_eq!(decode_path("a%2"), Path::new("a%2"));
+        assert_eq!(decode_path("a%zz%41"), Path::new("a%zzA"));
+    }
+
+    #[test]
+    fn test_numbered_name() {
+        assert_eq!(numbered_name(OsStr::new("a.txt"), 1), "a.txt");
+        assert_eq!(numbered_name(OsStr::new("a.txt"), 2), "a.2.txt");
+        assert_eq!(numbered_name(OsStr::new("a.tar.gz"), 3), "a.tar.3.gz");
+        assert_eq!(numbered_name(OsStr::new("dir"), 2), "dir.2");
+        assert_eq!(numbered_name(OsStr::new(".bashrc"), 2), ".bashrc.2");
+    }
+
+    #[test]
+    fn test_migrate_old_trash() {
+        let temp = TestDir::new();
+        let old_trash = temp.path().join("old");
+        let home_trash = temp.path().join("Trash");
+        fs::create_dir_all(old_trash.join("1640995200_dir")).unwrap();
+        fs::write(old_trash.join("1640995200_dir/file"), "a").unwrap();
+        fs::write(old_trash.join("1640995200_a.txt"), "b").unwrap();
+        fs::write(old_trash.join("other"), "c").unwrap();
+        make_trash(&home_trash).unwrap();
+        fs::write(files_dir(&home_trash).join("a.txt"), "d").unwrap();
+
+        let home = Path::new("/home/user");
+        assert_eq!(migrate_old_trash(&old_trash, &home_trash, home).unwrap(), 3);
+        assert!(!old_trash.exists());
+        assert!(files_dir(&home_trash).join("dir/file").is_file());
+        assert!(files_dir(&home_trash).join("other").is_file());
+        let info = read_info(&home_trash, OsStr::new("a.2.txt")).unwrap();
+        assert_eq!(info.original_path, home.join("a.txt"));
+        let date = Local.timestamp(1640995200, 0).naive_local();
+        assert_eq!(info.deletion_date, Some(date));
+    }
+}
@@ -0,0 +1,88 @@
+use super::config::SortKey;
+use super::progress::Progress;
+use super::state::{push_items, FileType, ItemInfo};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+//Item shown in the disk usage view.
+#[derive(Debug, Clone)]
+pub struct UsageItem {
+    pub item: ItemInfo,
+    pub size: u64,
+}
+
+//Cumulative sizes of the directories in the scanned subtree.
+#[derive(Debug, Clone)]
+pub struct Usage {
+    pub root: PathBuf,
+    sizes: HashMap<PathBuf, u64>,
+}
+
+impl Usage {
+    //Walk the subtree once. Symlinks are not followed, and unreadable items are skipped.
+    pub fn scan(root: &Path, progress: &Progress) -> std::io::Result<Usage> {
+        let mut sizes = HashMap::new();
+        for entry in walkdir::WalkDir::new(root).into_iter().flatten() {
+            progress.check()?;
+            //directories come before their contents
+            if entry.file_type().is_dir() {
+                sizes.insert(entry.path().to_path_buf(), 0);
+                continue;
+            }
+            let len = match entry.metadata() {
+                Ok(metadata) => metadata.len(),
+                Err(_) => continue,
+            };
+            for ancestor in entry.path().ancestors().skip(1) {
+                if let Some(size) = sizes.get_mut(ancestor) {
+                    *size += len;
+                }
+                if ancestor == root {
+                    break;
+                }
+            }
+            progress.add_total(len, 1);
+            progress.add_bytes(len);
+            progress.add_file();
+        }
+        Ok(Usage {
+            root: root.to_path_buf(),
+            sizes,
+        })
+    }
+
+    pub fn size(&self, dir: &Path) -> u64 {
+        self.sizes.get(dir).copied().unwrap_or(0)
+    }
+
+    //Items in the directory, largest first.
+    //Items made after the scan are listed with their own size.
+    pub fn list(&self, dir: &Path) -> std::io::Result<Vec<UsageItem>> {
+        let mut items: Vec<UsageItem> = push_items(dir, &SortKey::Name)?
+            .into_iter()
+            .map(|item| {
+                let size = match item.file_type {
+                    FileType::Directory => self.size(&item.file_path),
+                    FileType::File | FileType::Symlink => item.file_size,
+                };
+                UsageItem { item, size }
+            })
+            .collect();
+        items.sort_by_key(|usage_item| std::cmp::Reverse(usage_item.size));
+        Ok(items)
+    }
+
+    //Forget the removed item, so that sizes of the directories above it go down.
+    pub fn remove(&mut self, usage_item: &UsageItem) {
+        let path = &usage_item.item.file_path;
+        self.sizes.retain(|dir, _| !dir.starts_with(path));
+        for ancestor in path.ancestors().skip(1) {
+            if let Some(size) = self.sizes.get_mut(ancestor) {
+                *size = size.saturating_sub(usage_item.size);
+            }
+            if ancestor == self.root {
+                break;
+            }
+        }
+    }
+}